@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+
+use crate::meta::{famfs_log, LogEntry};
+use crate::namespace::{Namespace, Node, NodeKind};
+use crate::relpath::RelPath;
+
+// Access masks, the same bits as access(2)
+pub const ACCESS_READ: u32 = libc::R_OK as u32;
+pub const ACCESS_WRITE: u32 = libc::W_OK as u32;
+pub const ACCESS_EXEC: u32 = libc::X_OK as u32;
+
+/// Who is asking for access: the effective uid and gid of the caller and
+/// its supplementary groups
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>
+}
+
+impl Credentials {
+    pub fn new(uid: u32, gid: u32, groups: Vec<u32>) -> Credentials {
+        Credentials { uid, gid, groups }
+    }
+
+    // The credentials of this process
+    pub fn current() -> std::io::Result<Credentials> {
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        let ngroups = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        if ngroups < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut groups = vec![0; ngroups as usize];
+        let ngroups = unsafe { libc::getgroups(ngroups, groups.as_mut_ptr()) };
+        if ngroups < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        groups.truncate(ngroups as usize);
+
+        Ok(Credentials { uid, gid, groups })
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// Checks `mask` (ACCESS_* bits) against a mode and owner like the
+    /// kernel does: the owner bits apply to the owner, then the group bits
+    /// to group members, then the other bits to everyone else. Root may
+    /// read and write anything and execute anything with an x bit set,
+    /// directories are always searchable for it.
+    pub fn allows(&self, kind: NodeKind, mode: u32, uid: u32, gid: u32, mask: u32) -> bool {
+        if self.uid == 0 {
+            let any_exec = mode & 0o111 != 0 || kind == NodeKind::Dir;
+            return mask & ACCESS_EXEC == 0 || any_exec;
+        }
+
+        let bits = if self.uid == uid {
+            (mode >> 6) & 0o7
+        } else if self.in_group(gid) {
+            (mode >> 3) & 0o7
+        } else {
+            mode & 0o7
+        };
+
+        bits & mask == mask
+    }
+}
+
+// (kind, mode, uid, gid) of a namespace node, from its last setattr entry
+// or else the entry it came from
+pub(crate) fn node_attrs(log: &famfs_log, node: &Node) -> (NodeKind, u32, u32, u32) {
+    if let Some(attr_index) = node.attr_index
+        && let LogEntry::SetAttr { attr } = unsafe { log.get_entry_ref(attr_index as usize) }.get_entry_type()
+    {
+        return (node.kind, attr.mode(), attr.uid(), attr.gid());
+    }
+
+    match unsafe { log.get_entry_ref(node.index as usize) }.get_entry_type() {
+        LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => (NodeKind::File, file_meta.fm_mode, file_meta.fm_uid, file_meta.fm_gid),
+        LogEntry::MakeDir { dir_meta } => (NodeKind::Dir, dir_meta.mode(), dir_meta.uid(), dir_meta.gid()),
+        LogEntry::Symlink { symlink } => (NodeKind::Symlink, 0o777, symlink.uid(), symlink.gid()),
+        _ => unreachable!("the namespace only holds files, directories and symlinks"),
+    }
+}
+
+/// Resolves `path` like `Namespace::resolve` as `creds`, every directory
+/// the lookup goes through has to be searchable. Fails with -EACCES on
+/// the first one that isn't.
+pub fn resolve_as(ns: &Namespace, log: &famfs_log, path: &Path, creds: &Credentials, follow: bool) -> Result<PathBuf, i64> {
+    ns.resolve_with(log, path, follow, |node| {
+        let (kind, mode, uid, gid) = node_attrs(log, node);
+        if !creds.allows(kind, mode, uid, gid, ACCESS_EXEC) {
+            return Err(-(libc::EACCES as i64));
+        }
+        Ok(())
+    })
+}
+
+/// Looks up `path` as `creds`: every directory on the way has to be
+/// searchable and the node itself has to allow `mask`. The root has no
+/// metadata in the log and can be searched by everyone.
+pub fn check_access(ns: &Namespace, log: &famfs_log, path: &Path, creds: &Credentials, mask: u32) -> Result<Node, i64> {
+    let path = RelPath::new(path).map_err(|err| err.errno())?;
+
+    let mut dir = path.parent();
+    let mut dirs = Vec::new();
+    while let Some(parent) = dir {
+        dir = parent.parent();
+        dirs.push(parent);
+    }
+
+    // outermost first, so the first failing component is the one reported
+    for parent in dirs.iter().rev() {
+        let node = ns.lookup(parent.as_path()).ok_or(-(libc::ENOENT as i64))?;
+        let (kind, mode, uid, gid) = node_attrs(log, node);
+
+        if kind != NodeKind::Dir {
+            return Err(-(libc::ENOTDIR as i64));
+        }
+        if !creds.allows(kind, mode, uid, gid, ACCESS_EXEC) {
+            return Err(-(libc::EACCES as i64));
+        }
+    }
+
+    let node = *ns.lookup(path.as_path()).ok_or(-(libc::ENOENT as i64))?;
+    let (kind, mode, uid, gid) = node_attrs(log, &node);
+    if !creds.allows(kind, mode, uid, gid, mask) {
+        return Err(-(libc::EACCES as i64));
+    }
+
+    Ok(node)
+}
@@ -1,6 +1,8 @@
+use std::fmt;
 use std::vec::Vec;
-use crate::meta::famfs_log;
+use crate::meta::{famfs_log, famfs_simple_extent, LogEntry};
 use crate::meta::FAMFS_SUPERBLOCK_SIZE;
+use crate::namespace::{relpath_to_path, Namespace};
 
 const BYTE_SIZE: u64 = (size_of::<u8>() as u64) * 8;
 const BYTE_SHIFT: u64 = 3;
@@ -9,15 +11,17 @@ pub(crate) struct Bitmap {
     backing: Vec<u8>,
     alloc_unit: u64,
     len: u64, // the number of bits 
+    errors: u64, // allocation units claimed more than once while building
+    alloc_sum: u64 // bytes allocated, kept up to date by alloc and release
 }
 
 impl Bitmap {
     pub fn build_bitmap(
-        log: *mut famfs_log,
+        log: *const famfs_log,
         alloc_unit: u64,
         dev_size_in: u64
     ) -> Bitmap {
-        let mut logr = unsafe {log.as_mut().unwrap()};
+        let logr = unsafe {log.as_ref().unwrap()};
         let nbits = dev_size_in.div_ceil(alloc_unit) as usize;
         let slots = (nbits + BYTE_SIZE as usize) >> (BYTE_SHIFT); // find the number of slots requried for the bitmap
 
@@ -28,52 +32,55 @@ impl Bitmap {
         let mut bm = Bitmap {
             backing: backing,
             alloc_unit: alloc_unit,
-            len: nbits as u64
+            len: nbits as u64,
+            errors: 0,
+            alloc_sum: 0
         };
         bm.insert_meta_files(logr.byte_len(), &mut alloc_sum);
 
+        // resizes name their file by path, so follow the namespace along
+        let mut ns = Namespace::new();
+
         for i in 0..logr.len() {
             let le = unsafe { logr.get_entry_ref(i as usize) };
             match le.get_entry_type() {
-                super::meta::LogEntry::File { file_meta } => {
-                    let extent = file_meta.get_extent();
-
-                    match extent {
-                        super::meta::Extent::Simple { extent } => {
-                            for j in 0..(extent.fmap_nextents as usize) {
-                                let indexed_extent = extent.se[j];
-                                let offset = indexed_extent.se_offset;
-                                let len = indexed_extent.se_len;
-
-                                debug_assert!(offset % alloc_unit == 0);
-
-                                let rc = bm.set_extent(offset, len, &mut alloc_sum);
-                                errors += rc;
-                            }
-                        },
-                        super::meta::Extent::Interleaved { extent } => {
-                            let nstripes = extent.fmap_niext;
-                            for j in 0..(nstripes as usize) {
-                                let stripe = extent.se[j];
-                                for k in 0..(stripe.ie_nstrips as usize) {
-                                    let indexed_stripe = stripe.ie_strips[k];
-                                    
-                                    let offset = indexed_stripe.se_offset;
-                                    let len = indexed_stripe.se_len;
-
-                                    let rc = bm.set_extent(offset, len, &mut alloc_sum);
-                                    errors += rc;
-                                }
-                            }
-                        },
+                LogEntry::File { file_meta } => {
+                    for extent in file_meta.extent_list() {
+                        let rc = bm.set_extent(extent.se_offset, extent.se_len, &mut alloc_sum);
+                        errors += rc;
+                    }
+                },
+                // the units the file held before stay its own, anything
+                // else the new map claims has to be free
+                LogEntry::Resize { file_meta } => {
+                    let own = ns.lookup(&relpath_to_path(&file_meta.fm_relpath))
+                        .and_then(|node| match unsafe { logr.get_entry_ref(node.index as usize) }.get_entry_type() {
+                            LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => Some(file_meta.extent_list()),
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+
+                    for extent in file_meta.extent_list() {
+                        errors += bm.claim_extent(extent.se_offset, extent.se_len, &own, &mut alloc_sum);
                     }
                 },
-                super::meta::LogEntry::MakeDir { dir_meta: _ } => continue,
-                super::meta::LogEntry::Delete => panic!("invalid file_ext type"),
-                super::meta::LogEntry::Invalid => panic!("invalid file_ext type"),
+                LogEntry::Delete { file_meta } => {
+                    for extent in file_meta.extent_list() {
+                        bm.clear_extent(extent.se_offset, extent.se_len, &mut alloc_sum);
+                    }
+                },
+                LogEntry::MakeDir { dir_meta: _ } => {},
+                LogEntry::Rename { rename: _ } => {},
+                LogEntry::SetAttr { attr: _ } => {},
+                LogEntry::Symlink { symlink: _ } => {},
+                LogEntry::Invalid => {},
             }
+            ns.apply_entry(i, le);
         }
 
+        bm.errors = errors;
+        bm.alloc_sum = alloc_sum;
+
         bm
     }
     
@@ -85,6 +92,14 @@ impl Bitmap {
         self.alloc_unit
     }
 
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    pub fn alloc_sum(&self) -> u64 {
+        self.alloc_sum
+    }
+
     pub fn set(&mut self, index: u64) {
         let byte_num = (index >> BYTE_SHIFT) as usize;
         let bit_num = (index % BYTE_SIZE) as usize;
@@ -116,7 +131,7 @@ impl Bitmap {
         let byte_num = (index >> BYTE_SHIFT) as usize;
         let bit_num = (index % BYTE_SIZE) as usize;
 
-        if !(self.backing[byte_num] & (1 << bit_num)) > 0 {
+        if (self.backing[byte_num] & (1 << bit_num)) == 0 {
             return false;
         }
 
@@ -136,6 +151,11 @@ impl Bitmap {
         let mut errors = 0;
 
         for k in page_num..(page_num + np) {
+            if k >= self.len {
+                errors+=1;
+                continue;
+            }
+
             let rc = self.test_and_set(k);
             if rc {
                 *alloc_sum += self.alloc_unit;
@@ -148,6 +168,75 @@ impl Bitmap {
         errors
     }
     
+    // Like set_extent, but units inside `own` (the extents a file held
+    // before its map was relogged) are already the file's and are skipped
+    pub fn claim_extent(&mut self, offset: u64, len: u64, own: &[famfs_simple_extent], alloc_sum: &mut u64) -> u64 {
+        let page_num = offset / self.alloc_unit;
+        let np = len.div_ceil(self.alloc_unit);
+        let mut errors = 0;
+
+        for k in page_num..(page_num + np) {
+            let unit = k * self.alloc_unit;
+            if own.iter().any(|se| se.se_offset <= unit && unit < se.se_offset + se.se_len) {
+                continue;
+            }
+
+            if k >= self.len {
+                errors += 1;
+            } else if self.test_and_set(k) {
+                *alloc_sum += self.alloc_unit;
+            } else {
+                errors += 1;
+            }
+        }
+
+        errors
+    }
+
+    // Claims the units covering [offset, offset + size) if every one of
+    // them is free, for growing an extent in place
+    pub fn alloc_at(&mut self, offset: u64, size: u64) -> bool {
+        let start = offset / self.alloc_unit;
+        let end = start + size.div_ceil(self.alloc_unit);
+        if end > self.len || (start..end).any(|k| self.test(k)) {
+            return false;
+        }
+
+        for k in start..end {
+            self.set(k);
+        }
+        self.alloc_sum += (end - start) * self.alloc_unit;
+
+        true
+    }
+
+    pub fn clear_extent(&mut self, offset: u64, len: u64, alloc_sum: &mut u64) -> u64 {
+        let page_num = offset / self.alloc_unit;
+        let np = len.div_ceil(self.alloc_unit);
+        let mut errors = 0;
+
+        for k in page_num..(page_num + np) {
+            if k < self.len && self.test_and_clear(k) {
+                *alloc_sum -= self.alloc_unit;
+            }
+            else {
+                errors+=1;
+            }
+        }
+
+        errors
+    }
+
+    // Clears an extent freed since the bitmap was built, it no longer
+    // counts towards alloc_sum
+    pub fn release_extent(&mut self, offset: u64, len: u64) -> u64 {
+        let mut alloc_sum = self.alloc_sum;
+        let errors = self.clear_extent(offset, len, &mut alloc_sum);
+        self.alloc_sum = alloc_sum;
+
+        errors
+    }
+
     pub fn alloc_is_interleaved() -> bool {
         todo!()
     }
@@ -170,11 +259,13 @@ impl Bitmap {
         let alloc_bits = alloc_size.div_ceil(self.alloc_unit);
         let start_index = *cur_pos / self.alloc_unit;
         let range_size_bits = if range_size == 0 {self.len} else {range_size.div_ceil(self.alloc_unit)};
+        // the range can't run past the end of the device
+        let end_index = std::cmp::min(start_index.saturating_add(range_size_bits), self.len);
 
-        'label: for i in start_index..self.len {
+        'label: for i in start_index..end_index {
             if self.test(i) {continue}
 
-            let rem = start_index + range_size_bits - i;
+            let rem = end_index - i;
 
             if alloc_bits > rem {return None}
 
@@ -185,6 +276,7 @@ impl Bitmap {
             for j in i..(i + alloc_bits) {
                 self.set(j);
             } 
+            self.alloc_sum += alloc_bits * self.alloc_unit;
 
             *cur_pos = (i + alloc_bits) * self.alloc_unit;
 
@@ -206,4 +298,13 @@ impl Bitmap {
             assert!(self.test_and_clear(i));
         }
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Bitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.len {
+            write!(f, "{}", if self.test(i) {1} else {0})?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::sync::atomic::{fence, Ordering};
+#[cfg(target_arch = "x86_64")]
+use std::sync::OnceLock;
+
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// Whether stores other hosts make to the shared memory show up in this
+/// host's caches. Without that (CXL shared memory usually can't snoop
+/// other hosts) reads have to drop cached lines first or they may see
+/// stale data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Coherence {
+    #[default]
+    NonCoherent,
+    Coherent
+}
+
+impl Coherence {
+    // Makes the next loads from the range see other hosts' stores
+    pub fn invalidate(self, addr: *const u8, len: usize) {
+        if self == Coherence::NonCoherent {
+            invalidate_range(addr, len);
+        }
+    }
+}
+
+/// How stores to a mapping are made durable. DAX memory only needs the
+/// cache lines written back, a mapped file (an image rather than a dax
+/// device) needs its dirty pages synced to the file too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Persistence {
+    #[default]
+    WriteBack,
+    Msync
+}
+
+impl Persistence {
+    // Makes [addr, addr + len) durable, the range has to lie in a mapping
+    pub fn persist(self, addr: *const u8, len: usize) -> std::io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        match self {
+            Persistence::WriteBack => {
+                flush_range(addr, len);
+                Ok(())
+            },
+            Persistence::Msync => {
+                // msync wants a page aligned start
+                let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+                let start = addr as usize & !(page - 1);
+                let rc = unsafe { libc::msync(start as *mut libc::c_void, addr as usize + len - start, libc::MS_SYNC) };
+                if rc != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// Drops any cached copies of [addr, addr + len) so the next load is served
+// from memory. Needed when another host may have written the range.
+pub fn invalidate_range(addr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+
+    let start = addr as usize & !(CACHE_LINE_SIZE - 1);
+    let end = addr as usize + len;
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        // clflush writes back and invalidates, there is no invalidate-only
+        // instruction available to userspace
+        fence(Ordering::SeqCst);
+        for line in (start..end).step_by(CACHE_LINE_SIZE) {
+            unsafe { core::arch::x86_64::_mm_clflush(line as *const u8) };
+        }
+        unsafe { core::arch::x86_64::_mm_mfence() };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        fence(Ordering::SeqCst);
+        for line in (start..end).step_by(CACHE_LINE_SIZE) {
+            unsafe { core::arch::asm!("dc civac, {}", in(reg) line) };
+        }
+        unsafe { core::arch::asm!("dsb sy") };
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let _ = (start, end);
+        fence(Ordering::SeqCst);
+    }
+}
+
+// The best write back instruction the cpu has, clwb keeps the line
+// cached, clflushopt doesn't, and neither is ordered like clflush is
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteBack {
+    Clflush,
+    Clflushopt,
+    Clwb
+}
+
+#[cfg(target_arch = "x86_64")]
+fn write_back() -> WriteBack {
+    static INSN: OnceLock<WriteBack> = OnceLock::new();
+
+    *INSN.get_or_init(|| {
+        use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+        if __cpuid(0).eax < 7 {
+            return WriteBack::Clflush;
+        }
+        let ebx = __cpuid_count(7, 0).ebx;
+        if ebx & (1 << 24) != 0 {
+            WriteBack::Clwb
+        } else if ebx & (1 << 23) != 0 {
+            WriteBack::Clflushopt
+        } else {
+            WriteBack::Clflush
+        }
+    })
+}
+
+// Writes back any dirty cached copies of [addr, addr + len) and waits for
+// the write back to complete, so the range is durable in memory.
+pub fn flush_range(addr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+
+    let start = addr as usize & !(CACHE_LINE_SIZE - 1);
+    let end = addr as usize + len;
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let insn = write_back();
+        for line in (start..end).step_by(CACHE_LINE_SIZE) {
+            match insn {
+                WriteBack::Clwb => unsafe { core::arch::asm!("clwb [{}]", in(reg) line, options(nostack, preserves_flags)) },
+                WriteBack::Clflushopt => unsafe { core::arch::asm!("clflushopt [{}]", in(reg) line, options(nostack, preserves_flags)) },
+                WriteBack::Clflush => unsafe { core::arch::x86_64::_mm_clflush(line as *const u8) },
+            }
+        }
+        unsafe { core::arch::x86_64::_mm_sfence() };
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        for line in (start..end).step_by(CACHE_LINE_SIZE) {
+            unsafe { core::arch::asm!("dc cvac, {}", in(reg) line) };
+        }
+        unsafe { core::arch::asm!("dsb sy") };
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let _ = (start, end);
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst` with stores that skip the cache
+/// where the cpu has them, and waits for them to reach memory. Elsewhere,
+/// and for overlapping ranges, it copies and then flushes `dst`.
+///
+/// # Safety
+/// Both ranges must be valid for `len` bytes
+pub unsafe fn copy_nontemporal(src: *const u8, dst: *mut u8, len: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if (src as usize) >= dst as usize + len || (dst as usize) >= src as usize + len {
+        use core::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_sfence, _mm_stream_si128};
+
+        // streaming stores need 16 byte aligned destinations, the ragged
+        // ends go through the cache
+        let head = std::cmp::min(dst.align_offset(16), len);
+        let body = (len - head) & !15;
+        let tail = head + body;
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(src, dst, head);
+            for off in (head..tail).step_by(16) {
+                let chunk = _mm_loadu_si128(src.add(off).cast::<__m128i>());
+                _mm_stream_si128(dst.add(off).cast::<__m128i>(), chunk);
+            }
+            std::ptr::copy_nonoverlapping(src.add(tail), dst.add(tail), len - tail);
+        }
+
+        flush_range(dst, head);
+        flush_range(unsafe { dst.add(tail) }, len - tail);
+        unsafe { _mm_sfence() };
+        return;
+    }
+
+    unsafe { std::ptr::copy(src, dst, len) };
+    flush_range(dst, len);
+}
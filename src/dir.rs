@@ -0,0 +1,129 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::meta::famfs_log;
+use crate::metadata::FamfsMetadata;
+use crate::namespace::{Namespace, Node, NodeKind};
+use crate::relpath::RelPath;
+
+// A file or directory found by read_dir or walk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FamfsDirEntry {
+    path: PathBuf,
+    metadata: FamfsMetadata
+}
+
+impl FamfsDirEntry {
+    // Relative to the root of the filesystem
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or(self.path.as_os_str())
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.metadata.kind()
+    }
+
+    pub fn metadata(&self) -> &FamfsMetadata {
+        &self.metadata
+    }
+}
+
+/// A depth first walk of everything below a directory, each directory
+/// comes before its contents. Filters only drop entries from the
+/// output, the walk still descends into directories that are filtered.
+pub struct Walk {
+    entries: std::vec::IntoIter<FamfsDirEntry>,
+    kind: Option<NodeKind>,
+    min_len: u64,
+    max_len: u64
+}
+
+impl Walk {
+    pub fn kind(mut self, kind: NodeKind) -> Walk {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn files(self) -> Walk {
+        self.kind(NodeKind::File)
+    }
+
+    pub fn dirs(self) -> Walk {
+        self.kind(NodeKind::Dir)
+    }
+
+    // Only files of at least len bytes, implies files()
+    pub fn min_len(mut self, len: u64) -> Walk {
+        self.min_len = len;
+        self.files()
+    }
+
+    // Only files of at most len bytes, implies files()
+    pub fn max_len(mut self, len: u64) -> Walk {
+        self.max_len = len;
+        self.files()
+    }
+}
+
+impl Iterator for Walk {
+    type Item = FamfsDirEntry;
+
+    fn next(&mut self) -> Option<FamfsDirEntry> {
+        self.entries.by_ref().find(|entry| {
+            self.kind.is_none_or(|kind| entry.kind() == kind)
+                && (self.min_len..=self.max_len).contains(&entry.metadata.len())
+        })
+    }
+}
+
+// Where dir leads once the symlinks in it are followed, which has to be
+// a directory (or the root, the empty path)
+fn check_dir(ns: &Namespace, log: &famfs_log, dir: &Path) -> Result<PathBuf, i64> {
+    let dir = ns.resolve(log, dir, true)?;
+    if dir.as_os_str().is_empty() {
+        return Ok(dir);
+    }
+
+    match ns.lookup(&dir) {
+        Some(node) if node.kind == NodeKind::Dir => Ok(dir),
+        Some(_) => Err(-(libc::ENOTDIR as i64)),
+        None => Err(-(libc::ENOENT as i64)),
+    }
+}
+
+// Entries for the nodes below resolved, with paths under dir (normalized)
+// as the caller named it rather than where its links lead
+fn entries<'a>(
+    log: &'a famfs_log,
+    nodes: impl Iterator<Item = (&'a Path, &'a Node)> + 'a,
+    dir: &Path,
+    resolved: &'a Path
+) -> impl Iterator<Item = FamfsDirEntry> + 'a {
+    let dir = RelPath::new(dir).map_or(resolved.to_path_buf(), |dir| dir.as_path().to_path_buf());
+
+    nodes.map(move |(path, node)| FamfsDirEntry {
+        path: dir.join(path.strip_prefix(resolved).unwrap_or(path)),
+        metadata: FamfsMetadata::from_node(log, node)
+            .expect("the namespace only holds files, directories and symlinks"),
+    })
+}
+
+pub(crate) fn read_dir(ns: &Namespace, log: &famfs_log, dir: &Path) -> Result<Vec<FamfsDirEntry>, i64> {
+    let resolved = check_dir(ns, log, dir)?;
+    Ok(entries(log, ns.children(&resolved), dir, &resolved).collect())
+}
+
+pub(crate) fn walk(ns: &Namespace, log: &famfs_log, dir: &Path) -> Result<Walk, i64> {
+    let resolved = check_dir(ns, log, dir)?;
+
+    Ok(Walk {
+        entries: entries(log, ns.descendants(&resolved), dir, &resolved).collect::<Vec<_>>().into_iter(),
+        kind: None,
+        min_len: 0,
+        max_len: u64::MAX,
+    })
+}
@@ -0,0 +1,417 @@
+use std::io::{IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+use crate::cache::{self, Coherence, Persistence};
+
+/// The memory held by the handles open on a Famfs. A writable handle
+/// keeps every other handle off the bytes it maps, whichever file they
+/// belong to, so the slices handles lend out never alias within the
+/// process even if a damaged log gives two files the same space.
+#[derive(Debug, Default)]
+pub(crate) struct OpenFiles {
+    handles: Mutex<Handles>
+}
+
+#[derive(Debug, Default)]
+struct Handles {
+    next_id: u64,
+    held: Vec<Held>
+}
+
+#[derive(Debug)]
+struct Held {
+    id: u64,
+    write: bool,
+    ranges: Vec<(usize, usize)> // [start, end) addresses of the segments
+}
+
+impl OpenFiles {
+    // None if a writer holds any of the segments' bytes, or anyone does
+    // when asking to write. Otherwise the id to release them with.
+    fn acquire(&self, segments: &[(*mut u8, usize)], write: bool) -> Option<u64> {
+        let mut handles = self.handles.lock().unwrap();
+        let ranges: Vec<(usize, usize)> = segments.iter().map(|&(addr, len)| (addr as usize, addr as usize + len)).collect();
+
+        let overlaps = |held: &Held| held.ranges.iter().any(|&(start, end)| {
+            ranges.iter().any(|&(other_start, other_end)| start < other_end && other_start < end)
+        });
+        if handles.held.iter().any(|held| (write || held.write) && overlaps(held)) {
+            return None;
+        }
+
+        let id = handles.next_id;
+        handles.next_id += 1;
+        handles.held.push(Held { id, write, ranges });
+        Some(id)
+    }
+
+    fn release(&self, id: u64) {
+        let mut handles = self.handles.lock().unwrap();
+        handles.held.retain(|held| held.id != id);
+    }
+}
+
+/// How a handle's writes reach memory. Other hosts sharing the memory
+/// only see what has left this host's caches. On an image file rather
+/// than a dax device, writes in every mode are only synced to the file
+/// by flush().
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    // plain stores, flush() (or dropping the handle) writes them back
+    #[default]
+    Cached,
+    // plain stores written back before each write returns
+    WriteBack,
+    // streaming stores that skip the caches, done when each write returns
+    NonTemporal
+}
+
+impl WriteMode {
+    // How one range gets stored
+    fn store(self) -> unsafe fn(*const u8, *mut u8, usize) {
+        match self {
+            WriteMode::Cached => |src, dst, len| unsafe { std::ptr::copy(src, dst, len) },
+            WriteMode::WriteBack => |src, dst, len| {
+                unsafe { std::ptr::copy(src, dst, len) };
+                cache::flush_range(dst, len);
+            },
+            WriteMode::NonTemporal => cache::copy_nontemporal,
+        }
+    }
+}
+
+/// An open file, its bytes are the DAX memory of its extents. A file
+/// borrows the Famfs it came from so neither it nor the slices it lends
+/// out can outlive the mapping.
+///
+/// Other hosts sharing the memory may still change the bytes under a
+/// read only handle, famfs doesn't lock files across hosts. On non
+/// coherent memory reads drop the cached lines they cover first, so
+/// they see what other hosts wrote back before the read.
+pub struct FamfsFile<'a> {
+    segments: Vec<(*mut u8, usize)>, // (address, len) of each segment in file order
+    len: usize,
+    cur: usize,
+    writable: bool, // false for read only files and handles
+    id: u64, // what open knows the handle by
+    open: &'a OpenFiles,
+    mode: WriteMode,
+    dirty: Vec<(usize, usize)>, // [start, end) file ranges flush() has to write back
+    coherence: Coherence,
+    persistence: Persistence
+}
+
+// The segments lie in the mapping, which 'a keeps alive, and OpenFiles
+// keeps other handles in the process off the bytes of a writable file
+unsafe impl Send for FamfsFile<'_> {}
+unsafe impl Sync for FamfsFile<'_> {}
+
+impl<'a> FamfsFile<'a> {
+    /// Opens a file made of `segments`, None if `open` says another
+    /// handle is in the way. The file is writable if `write` is set,
+    /// reads it as `coherence` says and flushes as `persistence` says.
+    ///
+    /// # Safety
+    /// Each segment must be mapped for 'a, and every handle on the
+    /// mapping has to be opened through the same `open`
+    pub(crate) unsafe fn new(
+        open: &'a OpenFiles,
+        segments: Vec<(*mut u8, usize)>,
+        write: bool,
+        coherence: Coherence,
+        persistence: Persistence
+    ) -> Option<FamfsFile<'a>> {
+        let id = open.acquire(&segments, write)?;
+
+        Some(FamfsFile {
+            len: segments.iter().map(|(_, len)| len).sum(),
+            segments,
+            cur: 0,
+            writable: write,
+            id,
+            open,
+            mode: WriteMode::default(),
+            dirty: Vec::new(),
+            coherence,
+            persistence,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn coherence(&self) -> Coherence {
+        self.coherence
+    }
+
+    // Handles start out with the coherence of their Famfs
+    pub fn set_coherence(&mut self, coherence: Coherence) {
+        self.coherence = coherence;
+    }
+
+    // The whole file, None unless it lives in a single extent. The bytes
+    // are as fresh as the call, later stores by other hosts may not show.
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        match self.segments[..] {
+            [] => Some(&[]),
+            [(addr, len)] => {
+                self.coherence.invalidate(addr, len);
+                Some(unsafe { std::slice::from_raw_parts(addr, len) })
+            },
+            _ => None,
+        }
+    }
+
+    pub fn write_mode(&self) -> WriteMode {
+        self.mode
+    }
+
+    // Writes made before the switch are still written back by flush()
+    pub fn set_write_mode(&mut self, mode: WriteMode) {
+        self.mode = mode;
+    }
+
+    // Also None if the file isn't writable. Stores through the slice are
+    // plain cached ones whatever the write mode, flush() writes them back.
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        if !self.writable {
+            return None;
+        }
+
+        let slice = match self.segments[..] {
+            [] => &mut [],
+            [(addr, len)] => {
+                self.coherence.invalidate(addr, len);
+                unsafe { std::slice::from_raw_parts_mut(addr, len) }
+            },
+            _ => return None,
+        };
+        self.mark_dirty(0, self.len);
+
+        Some(slice)
+    }
+
+    /// The file's bytes one extent at a time (one chunk at a time for
+    /// interleaved files), in file order. Each segment is as fresh as the
+    /// call to next that returned it.
+    pub fn segments(&self) -> FamfsSegments<'_> {
+        FamfsSegments { iter: self.segments.iter(), coherence: self.coherence }
+    }
+
+    // Copies a range out of the file into dst
+    fn load(&self) -> impl Fn(*const u8, *mut u8, usize) {
+        let coherence = self.coherence;
+        move |src, dst, len| {
+            coherence.invalidate(src, len);
+            unsafe { std::ptr::copy(src, dst, len) };
+        }
+    }
+
+    // (address, len) of the file's bytes from pos to the end of the file,
+    // a segment at a time
+    fn ranges(&self, pos: usize) -> impl Iterator<Item = (*mut u8, usize)> + '_ {
+        let mut start = 0;
+
+        self.segments.iter().filter_map(move |&(addr, len)| {
+            let skip = pos.saturating_sub(start);
+            start += len;
+            (skip < len).then(|| (unsafe { addr.add(skip) }, len - skip))
+        })
+    }
+
+    /// Copies up to `len` bytes from this file's position to `dst`'s
+    /// position, straight from one mapping to the other, and advances
+    /// both. Stops early at the end of either file.
+    pub fn copy_to(&mut self, dst: &mut FamfsFile<'_>, len: usize) -> std::io::Result<usize> {
+        if !dst.writable {
+            return Err(std::io::ErrorKind::PermissionDenied.into());
+        }
+
+        let src = self.ranges(self.cur).map(|(addr, len)| (addr as *const u8, len));
+        let (coherence, store) = (self.coherence, dst.mode.store());
+        let copy = |from, to, n| {
+            coherence.invalidate(from, n);
+            unsafe { store(from, to, n) };
+        };
+        let copied = unsafe { copy_ranges(dst.ranges(dst.cur), src, len, copy) };
+
+        self.cur += copied;
+        dst.wrote(copied);
+        Ok(copied)
+    }
+
+    // Moves the position past len bytes just written. Writes that left
+    // the cache still have to be synced to a mapped file.
+    fn wrote(&mut self, len: usize) {
+        if self.mode == WriteMode::Cached || self.persistence == Persistence::Msync {
+            self.mark_dirty(self.cur, self.cur + len);
+        }
+        self.cur += len;
+    }
+
+    fn mark_dirty(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+
+        // sequential writes grow the last range
+        match self.dirty.last_mut() {
+            Some(last) if start <= last.1 && end >= last.0 => *last = (last.0.min(start), last.1.max(end)),
+            _ => self.dirty.push((start, end)),
+        }
+    }
+
+    // Persists the file range [start, end) a segment at a time
+    fn persist(&self, start: usize, end: usize) -> std::io::Result<()> {
+        let mut left = end - start;
+        for (addr, len) in self.ranges(start) {
+            if left == 0 {
+                break;
+            }
+            let len = len.min(left);
+            self.persistence.persist(addr, len)?;
+            left -= len;
+        }
+        Ok(())
+    }
+
+    // copy_to the other way around
+    pub fn copy_from(&mut self, src: &mut FamfsFile<'_>, len: usize) -> std::io::Result<usize> {
+        src.copy_to(self, len)
+    }
+}
+
+/// Copies from the `src` ranges into the `dst` ranges, both (address,
+/// len) and filled in order, until either runs out or `limit` bytes are
+/// copied, each piece with `copy(src, dst, len)`. Returns the count
+/// copied.
+///
+/// # Safety
+/// Every range must be valid for its length and `copy` has to cope with
+/// overlapping ones
+unsafe fn copy_ranges(
+    mut dst: impl Iterator<Item = (*mut u8, usize)>,
+    mut src: impl Iterator<Item = (*const u8, usize)>,
+    limit: usize,
+    mut copy: impl FnMut(*const u8, *mut u8, usize)
+) -> usize {
+    let (mut dst_left, mut src_left) = (None, None);
+    let mut copied = 0;
+
+    while copied < limit {
+        let Some((to, to_len)) = dst_left.take().or_else(|| dst.next()) else { break };
+        let Some((from, from_len)) = src_left.take().or_else(|| src.next()) else { break };
+
+        let n = to_len.min(from_len).min(limit - copied);
+        copy(from, to, n);
+        copied += n;
+
+        if n < to_len {
+            dst_left = Some((unsafe { to.add(n) }, to_len - n));
+        }
+        if n < from_len {
+            src_left = Some((unsafe { from.add(n) }, from_len - n));
+        }
+    }
+
+    copied
+}
+
+impl Drop for FamfsFile<'_> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        self.open.release(self.id);
+    }
+}
+
+pub struct FamfsSegments<'f> {
+    iter: std::slice::Iter<'f, (*mut u8, usize)>,
+    coherence: Coherence
+}
+
+impl<'f> Iterator for FamfsSegments<'f> {
+    type Item = &'f [u8];
+
+    fn next(&mut self) -> Option<&'f [u8]> {
+        self.iter.next().map(|&(addr, len)| {
+            self.coherence.invalidate(addr, len);
+            unsafe { std::slice::from_raw_parts(addr as *const u8, len) }
+        })
+    }
+}
+
+// Reads and writes run across segment boundaries, vectored ones walk the
+// segments once for all the buffers
+impl Read for FamfsFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_vectored(&mut [IoSliceMut::new(buf)])
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
+        let dst = bufs.iter_mut().map(|buf| (buf.as_mut_ptr(), buf.len()));
+        let src = self.ranges(self.cur).map(|(addr, len)| (addr as *const u8, len));
+        let bytes_read = unsafe { copy_ranges(dst, src, usize::MAX, self.load()) };
+
+        self.cur += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+// Files can't grow, writes stop at the end of the file
+impl Write for FamfsFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        if !self.writable {
+            return Err(std::io::ErrorKind::PermissionDenied.into());
+        }
+
+        let src = bufs.iter().map(|buf| (buf.as_ptr(), buf.len()));
+        let store = self.mode.store();
+        let copy = |from, to, n| unsafe { store(from, to, n) };
+        let bytes_written = unsafe { copy_ranges(self.ranges(self.cur), src, usize::MAX, copy) };
+
+        self.wrote(bytes_written);
+        Ok(bytes_written)
+    }
+
+    // Makes every range written since the last flush durable: written back
+    // from the cache on DAX memory, synced to the file on a mapped image.
+    // Ranges that fail stay dirty for the next flush.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let dirty = std::mem::take(&mut self.dirty);
+        for (i, &(start, end)) in dirty.iter().enumerate() {
+            if let Err(err) = self.persist(start, end) {
+                self.dirty = dirty[i..].to_vec();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Seeking past the end is allowed, reads and writes there do nothing
+impl Seek for FamfsFile<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let cur = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => (self.len as u64).checked_add_signed(delta),
+            SeekFrom::Current(delta) => (self.cur as u64).checked_add_signed(delta),
+        };
+
+        let cur = cur.and_then(|cur| usize::try_from(cur).ok()).ok_or(std::io::ErrorKind::InvalidInput)?;
+        self.cur = cur;
+        Ok(cur as u64)
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::bitmap::Bitmap;
+use crate::meta::{famfs_log, famfs_log_entry, famfs_superblock, LogEntry, FAMFS_LOG_OFFSET};
+use crate::namespace::relpath_to_path;
+
+#[derive(Debug, Default, Clone)]
+pub struct FsckReport {
+    pub superblock_ok: bool,
+    pub log_ok: bool,
+    pub truncated: bool, // the image ends before the log or the device does
+    pub entries: u64,
+    pub bad_crc: u64,
+    pub bad_seqnum: u64, // seqnums that don't increase
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub deletes: u64,
+    pub renames: u64,
+    pub setattrs: u64,
+    pub resizes: u64,
+    pub duplicate_paths: Vec<PathBuf>,
+    pub bad_extents: u64, // misaligned or past the end of the device
+    pub overlapping_units: u64, // allocation units claimed more than once
+    pub allocated_bytes: u64
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.superblock_ok
+            && self.log_ok
+            && !self.truncated
+            && self.bad_crc == 0
+            && self.bad_seqnum == 0
+            && self.duplicate_paths.is_empty()
+            && self.bad_extents == 0
+            && self.overlapping_units == 0
+    }
+}
+
+impl fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "superblock:        {}", if self.superblock_ok { "ok" } else { "invalid" })?;
+        writeln!(f, "log:               {}", if self.log_ok { "ok" } else { "invalid" })?;
+        writeln!(f, "image:             {}", if self.truncated { "truncated" } else { "ok" })?;
+        writeln!(f, "entries:           {}", self.entries)?;
+        writeln!(f, "files:             {}", self.files)?;
+        writeln!(f, "directories:       {}", self.dirs)?;
+        writeln!(f, "symlinks:          {}", self.symlinks)?;
+        writeln!(f, "deletes:           {}", self.deletes)?;
+        writeln!(f, "renames:           {}", self.renames)?;
+        writeln!(f, "setattrs:          {}", self.setattrs)?;
+        writeln!(f, "resizes:           {}", self.resizes)?;
+        writeln!(f, "allocated bytes:   {}", self.allocated_bytes)?;
+        writeln!(f, "bad crcs:          {}", self.bad_crc)?;
+        writeln!(f, "bad seqnums:       {}", self.bad_seqnum)?;
+        writeln!(f, "bad extents:       {}", self.bad_extents)?;
+        writeln!(f, "overlapping units: {}", self.overlapping_units)?;
+        for path in &self.duplicate_paths {
+            writeln!(f, "duplicate path:    {}", path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+// Checks the superblock, the log and every entry in it. `len` is how many
+// bytes are mapped from the superblock on, nothing past it is read.
+pub fn fsck(sb: &famfs_superblock, log: &famfs_log, len: u64) -> FsckReport {
+    let mut report = FsckReport {
+        superblock_ok: sb.check_superblock(),
+        log_ok: log.check_log() && log.check_crc(),
+        ..Default::default()
+    };
+
+    if sb.log_offset() != FAMFS_LOG_OFFSET || sb.log_len() != log.byte_len() {
+        report.superblock_ok = false;
+    }
+
+    // a corrupt index would send us reading past the log
+    if log.len() > log.max_size().saturating_add(1) {
+        report.log_ok = false;
+    }
+
+    // without both there is nothing to check the entries against
+    if !report.superblock_ok || !report.log_ok {
+        return report;
+    }
+
+    // the entries in use, the whole log and the device have to fit in the
+    // image, a truncated one would have us reading off the end of it
+    let entries_end = log.len()
+        .checked_mul(size_of::<famfs_log_entry>() as u64)
+        .and_then(|len| len.checked_add(FAMFS_LOG_OFFSET + size_of::<famfs_log>() as u64));
+    report.truncated = entries_end.is_none_or(|end| end > len)
+        || FAMFS_LOG_OFFSET.checked_add(sb.log_len()).is_none_or(|end| end > len)
+        || sb.daxdev_size() as u64 > len;
+    if report.truncated {
+        return report;
+    }
+
+    let devsize = sb.daxdev_size() as u64;
+    let alloc_unit = sb.alloc_unit();
+    let mut live: HashSet<PathBuf> = HashSet::new();
+    let mut last_seqnum = None;
+
+    for i in 0..log.len() {
+        let entry = unsafe { log.get_entry_ref(i as usize) };
+        report.entries += 1;
+
+        if !entry.check_crc() {
+            report.bad_crc += 1;
+            continue;
+        }
+
+        if last_seqnum.is_some_and(|last| entry.seqnum() <= last) {
+            report.bad_seqnum += 1;
+        }
+        last_seqnum = Some(entry.seqnum());
+
+        match entry.get_entry_type() {
+            LogEntry::File { file_meta } => {
+                report.files += 1;
+
+                for extent in file_meta.extent_list() {
+                    if extent.se_offset % alloc_unit != 0 || extent.se_offset + extent.se_len > devsize {
+                        report.bad_extents += 1;
+                    }
+                }
+
+                let path = relpath_to_path(&file_meta.fm_relpath);
+                if !live.insert(path.clone()) {
+                    report.duplicate_paths.push(path);
+                }
+            },
+            LogEntry::MakeDir { dir_meta } => {
+                report.dirs += 1;
+
+                let path = relpath_to_path(&dir_meta.md_relpath);
+                if !live.insert(path.clone()) {
+                    report.duplicate_paths.push(path);
+                }
+            },
+            LogEntry::Delete { file_meta } => {
+                report.deletes += 1;
+                live.remove(&relpath_to_path(&file_meta.fm_relpath));
+            },
+            LogEntry::Rename { rename } => {
+                report.renames += 1;
+
+                let from = relpath_to_path(&rename.rn_relpath);
+                let to = relpath_to_path(&rename.rn_new_relpath);
+                let moved: Vec<PathBuf> = live.iter().filter(|path| path.starts_with(&from)).cloned().collect();
+
+                for path in moved {
+                    live.remove(&path);
+                    let target = to.join(path.strip_prefix(&from).unwrap_or(&path));
+                    if !live.insert(target.clone()) {
+                        report.duplicate_paths.push(target);
+                    }
+                }
+            },
+            LogEntry::Symlink { symlink } => {
+                report.symlinks += 1;
+
+                let path = relpath_to_path(&symlink.sl_relpath);
+                if !live.insert(path.clone()) {
+                    report.duplicate_paths.push(path);
+                }
+            },
+            LogEntry::Resize { file_meta } => {
+                report.resizes += 1;
+
+                for extent in file_meta.extent_list() {
+                    if extent.se_offset % alloc_unit != 0 || extent.se_offset + extent.se_len > devsize {
+                        report.bad_extents += 1;
+                    }
+                }
+            },
+            LogEntry::SetAttr { attr: _ } => report.setattrs += 1,
+            LogEntry::Invalid => continue,
+        }
+    }
+
+    if report.bad_extents == 0 {
+        let bitmap = Bitmap::build_bitmap(log, alloc_unit, devsize);
+        report.overlapping_units = bitmap.errors();
+        report.allocated_bytes = bitmap.alloc_sum();
+    }
+
+    report
+}
@@ -1,18 +1,27 @@
+use std::fs::File;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::{ffi::CString, ptr::NonNull};
 use std::cell::OnceCell;
 
-use crate::meta::{famfs_log_fmap, famfs_log_fmap_union_interleaved_extent, famfs_system_role, FAMFS_ALLOC_UNIT, FAMFS_LOG_OFFSET, FAMFS_MAX_PATHLEN, FAMFS_SUPERBLOCK_SIZE};
-use crate::{FamfsFile};
-use super::meta::{famfs_interleave_param, famfs_log, famfs_superblock, Extent};
+use crate::meta::{famfs_log_fmap, famfs_system_role, FAMFS_ALLOC_UNIT, FAMFS_MAX_SIMPLE_EXTENTS, FAMFS_SUPERBLOCK_SIZE, famfs_simple_extent, famfs_superblock};
+use crate::{FamfsFile, FamfsMetadataInterface};
+use crate::file::OpenFiles;
+use crate::cache::{Coherence, Persistence};
+use super::meta::{famfs_interleave_param, famfs_log, famfs_log_file_meta, fm_flags_writable, Extent, LogEntry, FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+use super::namespace::{Namespace, NodeKind};
+use super::access::node_attrs;
+use super::relpath::RelPath;
+use super::metadata::FamfsMetadata;
+use super::shadow::{emit_file_yaml, parse_file_yaml, shadow_path};
 use super::bitmap::Bitmap;
 
 #[repr(C)]
-pub struct famfs_locked_log {
+pub struct famfs_locked_log<'a> {
     devsize: u64, 
+    iface: &'a mut dyn FamfsMetadataInterface,
     logp: *mut famfs_log,
-    lfd: i32, // why store the error code at all??
+    lfd: File, // holds an exclusive flock for as long as the log is held
     famfs_type: famfs_system_role, 
     bitmap: OnceCell<Bitmap>,
     nbits: u64,
@@ -21,32 +30,70 @@ pub struct famfs_locked_log {
     interleave_param: famfs_interleave_param,
     mpt: PathBuf,
     shadow_root: PathBuf,
+    ns: Namespace, // indexes the log by path, caught up before each use
+    open: OpenFiles, // files handed out by get_file
 }
 
 #[repr(C)]
-pub(crate) struct famfs_log_stats {
-    n_entries: u64, 
-    bad_entries: u64,
-    f_logged: u64,
-    f_existed: u64,
-    f_created: u64,
-    f_errs: u64, 
-    d_logged: u64,
-    d_existed: u64,
-    d_created: u64, 
-    d_errs: u64,
-    yaml_errs: u64,
-    yaml_checked: u64
+#[derive(Debug, Default, Clone, Copy)]
+pub struct famfs_log_stats {
+    pub n_entries: u64, 
+    pub bad_entries: u64,
+    pub f_logged: u64,
+    pub f_existed: u64,
+    pub f_created: u64,
+    pub f_errs: u64, 
+    pub d_logged: u64,
+    pub d_existed: u64,
+    pub d_created: u64, 
+    pub d_errs: u64,
+    pub s_logged: u64,
+    pub yaml_errs: u64,
+    pub yaml_checked: u64
 }
 
-impl famfs_locked_log {
-    // takes from the log, without locking...
-    // though the synchronization required to actually lock 
-    pub unsafe fn from_log(logp: *mut famfs_log, sb: &famfs_superblock) -> famfs_locked_log {
+impl<'a> famfs_locked_log<'a> {
+    /// Takes the log of `iface` for mutation, blocking until `lfd` can be
+    /// exclusively flock'ed. `lfd` should be a file descriptor opened on
+    /// the backing file (image or dax device) of the log, every process
+    /// mutating the log must lock the same file. The lock is released when
+    /// the locked log is dropped.
+    ///
+    /// Note that flock locks belong to the open file description, so `lfd`
+    /// must come from its own `open` rather than a `dup` of a descriptor
+    /// another locked log may hold.
+    ///
+    /// # Safety
+    /// `iface` must hand out a mapped superblock and a log header
+    /// followed by its entries
+    pub unsafe fn from_log(iface: &'a mut dyn FamfsMetadataInterface, lfd: File) -> std::io::Result<famfs_locked_log<'a>> {
+        lfd.lock()?;
+        Ok(unsafe { Self::from_locked(iface, lfd) })
+    }
+
+    /// Like `from_log` but fails with `ErrorKind::WouldBlock` instead of
+    /// waiting if another process holds the log.
+    ///
+    /// # Safety
+    /// `iface` must hand out a mapped superblock and a log header
+    /// followed by its entries
+    pub unsafe fn try_from_log(iface: &'a mut dyn FamfsMetadataInterface, lfd: File) -> std::io::Result<famfs_locked_log<'a>> {
+        lfd.try_lock().map_err(|e| match e {
+            std::fs::TryLockError::Error(e) => e,
+            std::fs::TryLockError::WouldBlock => std::io::ErrorKind::WouldBlock.into(),
+        })?;
+        Ok(unsafe { Self::from_locked(iface, lfd) })
+    }
+
+    unsafe fn from_locked(iface: &'a mut dyn FamfsMetadataInterface, lfd: File) -> famfs_locked_log<'a> {
+        let devsize = unsafe { iface.superblock().as_ref() }.ts_daxdev.dd_size;
+        let logp = iface.log().as_ptr();
+
         famfs_locked_log {
-            devsize: sb.ts_daxdev.dd_size as u64,
-            logp: logp,
-            lfd: 0,
+            devsize,
+            iface,
+            logp,
+            lfd,
             famfs_type: famfs_system_role::FAMFS_MASTER,
             bitmap: OnceCell::new(),
             nbits: 0,
@@ -55,6 +102,8 @@ impl famfs_locked_log {
             interleave_param: famfs_interleave_param::default(),
             mpt: PathBuf::new(),
             shadow_root: PathBuf::new(),
+            ns: unsafe { Namespace::replay(logp) },
+            open: OpenFiles::default(),
         }
     }
 
@@ -74,12 +123,25 @@ impl famfs_locked_log {
         self.bitmap.get_mut().unwrap()
     }
 
-    fn file_alloc_contiguous(&mut self, size: u64) -> Result<famfs_log_fmap, i64> {
+    fn alloc_extent(&mut self, size: u64) -> Result<famfs_simple_extent, i64> {
         let mut cur_pos = self.cur_pos;
-        let offset = self.bitmap_mut().alloc_contiguous(size, &mut cur_pos, 0).ok_or(0)?;
+        // the search starts after the last allocation, space freed before
+        // it is only found by starting over
+        let offset = match self.bitmap_mut().alloc_contiguous(size, &mut cur_pos, 0) {
+            Some(offset) => offset,
+            None => {
+                cur_pos = 0;
+                self.bitmap_mut().alloc_contiguous(size, &mut cur_pos, 0).ok_or(-(libc::ENOSPC as i64))?
+            }
+        };
         self.cur_pos = cur_pos;
 
-        Ok(famfs_log_fmap::generate_simple_fmap(size, offset))
+        Ok(famfs_simple_extent { se_devindex: 0, se_offset: offset, se_len: size.div_ceil(self.alloc_unit) * self.alloc_unit })
+    }
+
+    fn file_alloc_contiguous(&mut self, size: u64) -> Result<famfs_log_fmap, i64> {
+        let extent = self.alloc_extent(size)?;
+        Ok(famfs_log_fmap::generate_simple_fmap(size, extent.se_offset))
     }
 
     fn file_alloc(&mut self, size: u64) -> Result<famfs_log_fmap, i64> {
@@ -87,64 +149,535 @@ impl famfs_locked_log {
         self.file_alloc_contiguous(size)
     }
 
+    // Brings the index up to date with what we've appended since its last use
+    fn catch_up(&mut self) -> &Namespace {
+        unsafe { self.ns.apply_new(self.logp) };
+        &self.ns
+    }
+
+    // A new file or directory needs a free path in an existing directory
+    fn check_create(&mut self, path: &RelPath) -> Result<(), i64> {
+        let ns = self.catch_up();
+        if ns.lookup(path.as_path()).is_some() {
+            return Err(-(libc::EEXIST as i64));
+        }
+
+        let Some(parent) = path.parent() else { return Ok(()) };
+        match ns.lookup(parent.as_path()) {
+            Some(node) if node.kind == NodeKind::Dir => Ok(()),
+            Some(_) => Err(-(libc::ENOTDIR as i64)),
+            None => Err(-(libc::ENOENT as i64)),
+        }
+    }
+
     pub fn make_file(        
         &mut self, 
         path: &Path,
         mode_t: u32,
         uid_t: u32,
         gid_t: u32,
-        size: u64
+        size: u64,
+        flags: u32 // FAMFS_FM_ALL_HOSTS_RO or FAMFS_FM_ALL_HOSTS_RW
     ) -> Result<(), i64> {
+        if flags != FAMFS_FM_ALL_HOSTS_RO && flags != FAMFS_FM_ALL_HOSTS_RW {
+            return Err(-(libc::EINVAL as i64));
+        }
+
+        let path = RelPath::new(path).map_err(|err| err.errno())?;
+        self.check_create(&path)?;
         let fmap = self.file_alloc(size)?;
-        unsafe { (*self.logp).log_file_create(&fmap, path, mode_t, uid_t, gid_t, size)?; }
+        unsafe { (*self.logp).log_file_create(&fmap, &path, mode_t, uid_t, gid_t, size, flags, self.iface)?; }
+
+        Ok(())
+    }
+
+    pub fn make_dir(
+        &mut self,
+        path: &Path,
+        mode_t: u32,
+        uid_t: u32,
+        gid_t: u32
+    ) -> Result<(), i64> {
+        let path = RelPath::new(path).map_err(|err| err.errno())?;
+        self.check_create(&path)?;
+        unsafe { (*self.logp).log_mkdir(&path, mode_t, uid_t, gid_t, self.iface)?; }
+
+        Ok(())
+    }
+
+    /// Creates a symlink at `path` pointing at `target`, which is kept as
+    /// given and only resolved on lookup, see `Namespace::resolve`. Fails
+    /// like make_dir, with -ENOENT for an empty target and -ENAMETOOLONG
+    /// for one of FAMFS_MAX_PATHLEN bytes or more.
+    pub fn make_symlink(&mut self, path: &Path, target: &Path, uid_t: u32, gid_t: u32) -> Result<(), i64> {
+        if target.as_os_str().is_empty() {
+            return Err(-(libc::ENOENT as i64));
+        }
+        if target.as_os_str().as_bytes().contains(&0) {
+            return Err(-(libc::EINVAL as i64));
+        }
+
+        let path = RelPath::new(path).map_err(|err| err.errno())?;
+        self.check_create(&path)?;
+        unsafe { (*self.logp).log_symlink(&path, target, uid_t, gid_t, self.iface)?; }
+
+        Ok(())
+    }
+
+    // Logs the removal of a file and releases its space. Symlinks are
+    // removed the same way, with a delete that frees nothing.
+    pub fn delete_file(&mut self, path: &Path) -> Result<(), i64> {
+        let path = RelPath::new(path).map_err(|err| err.errno())?;
+        let node = *self.catch_up().lookup(path.as_path()).ok_or(-(libc::ENOENT as i64))?;
+
+        // the file may have been renamed since it was logged
+        let log = unsafe { self.logp.as_mut().unwrap() };
+        let file_meta = match unsafe { log.get_entry_ref(node.index as usize) }.get_entry_type() {
+            LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => file_meta.with_relpath(&path),
+            LogEntry::Symlink { symlink } => {
+                let fmap = famfs_log_fmap::simple(&[]).unwrap();
+                famfs_log_file_meta::new(path.to_relpath(), 0, 0, 0o777, symlink.uid(), symlink.gid(), fmap)
+            },
+            _ => return Err(-(libc::EISDIR as i64)),
+        };
+
+        unsafe { log.log_delete(&file_meta, self.iface)?; }
+
+        // a bitmap built before the delete doesn't know about it
+        if let Some(bitmap) = self.bitmap.get_mut() {
+            for extent in file_meta.extent_list() {
+                bitmap.release_extent(extent.se_offset, extent.se_len);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the size of a file. Growing it first uses up the slack at the
+    /// end of its extents, then allocates one more extent (or grows the
+    /// last one if the space right after it is free). Shrinking keeps
+    /// every extent. The new size and map are logged in one resize entry
+    /// that replay puts in place of the file's last entry, so the file is
+    /// never missing from the log and the data stays where it is.
+    ///
+    /// Fails with -ENOENT, -EISDIR, -EOPNOTSUPP for interleaved files,
+    /// -EFBIG if the file already has FAMFS_MAX_SIMPLE_EXTENTS extents and
+    /// its last one can't grow, -ENOSPC if the device is full and 0 if the
+    /// log is. Space allocated for a resize that couldn't be logged is
+    /// given back.
+    pub fn set_len(&mut self, path: &Path, size: u64) -> Result<(), i64> {
+        let path = RelPath::new(path).map_err(|err| err.errno())?;
+        let node = *self.catch_up().lookup(path.as_path()).ok_or(-(libc::ENOENT as i64))?;
+
+        let log = unsafe { self.logp.as_ref().unwrap() };
+        let file_meta = match unsafe { log.get_entry_ref(node.index as usize) }.get_entry_type() {
+            LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => *file_meta,
+            LogEntry::MakeDir { .. } => return Err(-(libc::EISDIR as i64)),
+            _ => return Err(-(libc::EINVAL as i64)),
+        };
+        if !matches!(file_meta.get_extent(), Extent::Simple { .. }) {
+            return Err(-(libc::EOPNOTSUPP as i64));
+        }
+        if log.log_full() {
+            return Err(0);
+        }
+
+        let mut extents = file_meta.extent_list();
+        let capacity: u64 = extents.iter().map(|se| se.se_len).sum();
+        let mut new = None;
+        if size > capacity {
+            let needed = (size - capacity).div_ceil(self.alloc_unit) * self.alloc_unit;
+            let end = extents.last().map_or(0, |last| last.se_offset + last.se_len);
+
+            // growing the last extent in place doesn't use up the map
+            if !extents.is_empty() && self.bitmap_mut().alloc_at(end, needed) {
+                extents.last_mut().unwrap().se_len += needed;
+                new = Some(famfs_simple_extent { se_devindex: 0, se_offset: end, se_len: needed });
+            } else if extents.len() == FAMFS_MAX_SIMPLE_EXTENTS {
+                return Err(-(libc::EFBIG as i64));
+            } else {
+                let extent = self.alloc_extent(needed)?;
+                extents.push(extent);
+                new = Some(extent);
+            }
+        }
+
+        let fmap = famfs_log_fmap::simple(&extents).expect("one extent was added to a map with room for it");
+        let log = unsafe { self.logp.as_mut().unwrap() };
+        // the new entry takes over any setattr logged for the old one
+        let (_, mode, uid, gid) = node_attrs(log, &node);
+        let resized = famfs_log_file_meta::new(path.to_relpath(), size, file_meta.fm_flags, mode, uid, gid, fmap);
+
+        let logged = unsafe { log.log_resize(&resized, self.iface) };
+        if let (Err(_), Some(extent)) = (logged, new) {
+            self.bitmap_mut().release_extent(extent.se_offset, extent.se_len);
+        }
+
+        logged
+    }
+
+    /// Sets the permission bits of a file or directory, the owner stays.
+    /// Fails with -ENOENT, -EINVAL for bits outside 0o7777 and 0 if the
+    /// log is full.
+    pub fn set_permissions(&mut self, path: &Path, mode: u32) -> Result<(), i64> {
+        if mode & !0o7777 != 0 {
+            return Err(-(libc::EINVAL as i64));
+        }
+        self.set_attrs(path, Some(mode), None, None)
+    }
+
+    /// Sets the owner and/or group of a file or directory, None leaves
+    /// that one as it is. Fails with -ENOENT and 0 if the log is full.
+    pub fn set_owner(&mut self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(), i64> {
+        self.set_attrs(path, None, uid, gid)
+    }
+
+    // Logs all three attributes, taking the ones not given from the node
+    fn set_attrs(&mut self, path: &Path, mode: Option<u32>, uid: Option<u32>, gid: Option<u32>) -> Result<(), i64> {
+        let path = RelPath::new(path).map_err(|err| err.errno())?;
+        let node = *self.catch_up().lookup(path.as_path()).ok_or(-(libc::ENOENT as i64))?;
+
+        let log = unsafe { self.logp.as_mut().unwrap() };
+        let (_, old_mode, old_uid, old_gid) = node_attrs(log, &node);
+        unsafe {
+            log.log_setattr(
+                &path, mode.unwrap_or(old_mode), uid.unwrap_or(old_uid), gid.unwrap_or(old_gid), self.iface
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves a file or a directory, with everything under it, to `to`.
+    /// Only the rename is logged, the entries that created the nodes and
+    /// the data they point at stay where they are, so a file written
+    /// under a temporary name can be published in one step.
+    ///
+    /// Fails with -ENOENT if `from` doesn't exist, -EEXIST if `to` does,
+    /// -ENOENT or -ENOTDIR if the parent of `to` isn't a directory,
+    /// -EINVAL when moving a directory under itself and 0 if the log is
+    /// full. Renaming a path to itself does nothing.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), i64> {
+        let from = RelPath::new(from).map_err(|err| err.errno())?;
+        let to = RelPath::new(to).map_err(|err| err.errno())?;
+
+        if self.catch_up().lookup(from.as_path()).is_none() {
+            return Err(-(libc::ENOENT as i64));
+        }
+        if from == to {
+            return Ok(());
+        }
+        if to.as_path().starts_with(from.as_path()) {
+            return Err(-(libc::EINVAL as i64));
+        }
+        self.check_create(&to)?;
+
+        unsafe { (*self.logp).log_rename(&from, &to, self.iface)?; }
 
         Ok(())
     }
 
-    pub fn get_file(&self, path: &Path) -> Option<FamfsFile> {
+    // The namespace as seen by the holder of the lock, it can't change
+    // under us until the lock is dropped
+    pub fn namespace(&mut self) -> Namespace {
+        self.catch_up().clone()
+    }
+
+    // Looks path up like Famfs::stat
+    pub fn stat(&mut self, path: &Path) -> Option<FamfsMetadata> {
         let log = unsafe { self.logp.as_ref().unwrap() };
+        let ns = self.catch_up();
+        let node = *ns.lookup(&ns.resolve(log, path, false).ok()?)?;
+        FamfsMetadata::from_node(log, &node)
+    }
 
-        for i in 0..log.len() {
-            let entry = unsafe { log.get_entry_ref(i as usize) };
-            let entry_type = entry.get_entry_type();
-            match entry_type {
-                crate::meta::LogEntry::File { file_meta } => {
-                    let encoded_path = path.as_os_str().as_encoded_bytes();
-                    if encoded_path.len() > FAMFS_MAX_PATHLEN {
-                        return None;
+    pub fn get_file(&self, path: &Path) -> Option<FamfsFile<'_>> {
+        unsafe { get_file(self.logp, &self.open, &self.caught_up(), path, false, self.iface.persistence()) }
+    }
+
+    pub fn get_file_read_only(&self, path: &Path) -> Option<FamfsFile<'_>> {
+        unsafe { get_file(self.logp, &self.open, &self.caught_up(), path, true, self.iface.persistence()) }
+    }
+
+    // A copy of the index with our latest appends, for lookups through &self
+    fn caught_up(&self) -> Namespace {
+        let mut ns = self.ns.clone();
+        unsafe { ns.apply_new(self.logp) };
+        ns
+    }
+
+    /// Plays the log into a shadow filesystem under `shadow_root`, see
+    /// the shadow module for its layout. A shadow played earlier is
+    /// brought up to date: whatever the namespace no longer has is
+    /// removed, and yamls that don't match the log any more are rewritten
+    /// and counted as created. Every file yaml, new or existing, is read
+    /// back and checked against the log, mismatches count as yaml_errs.
+    pub fn logplay_shadow(&mut self, shadow_root: &Path) -> std::io::Result<famfs_log_stats> {
+        self.shadow_root = shadow_root.to_path_buf();
+        std::fs::create_dir_all(shadow_path(shadow_root, Path::new("")))?;
+
+        let ns = self.namespace();
+        let log = unsafe { self.logp.as_ref().unwrap() };
+        let mut stats = *ns.stats();
+        prune_shadow(&ns, shadow_root, Path::new(""), &mut stats)?;
+
+        // parents sort before their children so they are always made first
+        for (path, node) in ns.iter() {
+            let shadow = shadow_path(shadow_root, path);
+
+            // the yaml carries the path and attributes the file has now,
+            // not the logged ones
+            let (_, mode, uid, gid) = node_attrs(log, node);
+            let file_meta = match unsafe { log.get_entry_ref(node.index as usize) }.get_entry_type() {
+                LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => match RelPath::new(path) {
+                    Ok(relpath) => file_meta.with_relpath(&relpath).with_attrs(mode, uid, gid),
+                    Err(_) => file_meta.with_attrs(mode, uid, gid),
+                },
+                // the shadow format has no way to describe a symlink
+                LogEntry::Symlink { .. } => continue,
+                _ => {
+                    match std::fs::create_dir(&shadow) {
+                        Ok(()) => stats.d_created += 1,
+                        Err(_) if shadow.is_dir() => stats.d_existed += 1,
+                        Err(_) => stats.d_errs += 1,
                     }
+                    continue;
+                },
+            };
 
-                    if &file_meta.fm_relpath[..encoded_path.len()] == encoded_path {
-                        let base = match file_meta.get_extent() {
-                            Extent::Simple { extent } => {
-                                println!("extent.se[0].se_offset {}", extent.se[0].se_offset);
-                                unsafe {
-                                    self.logp
-                                        .cast::<u8>()
-                                        .offset(-(FAMFS_SUPERBLOCK_SIZE as isize))
-                                        .offset(extent.se[0].se_offset as isize)
-                                }
-                            },
-                            _ => return None,
-                        };
-                        return Some(FamfsFile {
-                            base: base,
-                            len: file_meta.fm_size as usize,
-                            cur: 0,
-                        })
+            let current = std::fs::read_to_string(&shadow).ok().and_then(|yaml| parse_file_yaml(&yaml).ok());
+            if current.as_ref() == Some(&file_meta) {
+                stats.f_existed += 1;
+            } else if std::fs::write(&shadow, emit_file_yaml(&file_meta)).is_ok() {
+                stats.f_created += 1;
+            } else {
+                stats.f_errs += 1;
+                continue;
+            }
+
+            stats.yaml_checked += 1;
+            let yaml = std::fs::read_to_string(&shadow).unwrap_or_default();
+            if parse_file_yaml(&yaml).ok().as_ref() != Some(&file_meta) {
+                stats.yaml_errs += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Plays the log onto a directory tree at the mount point `mpt`: each
+    /// directory is made and each file gets a placeholder of its size,
+    /// all with the logged mode and owner. Anything already there is left
+    /// alone. With `dry_run` nothing is touched, the stats and changes say
+    /// what would have been done.
+    pub fn logplay_mount(&mut self, mpt: &Path, dry_run: bool) -> std::io::Result<(famfs_log_stats, Vec<MountChange>)> {
+        if !mpt.is_dir() {
+            return Err(std::io::ErrorKind::NotADirectory.into());
+        }
+        self.mpt = mpt.to_path_buf();
+
+        let ns = self.namespace();
+        let log = unsafe { self.logp.as_ref().unwrap() };
+        let mut stats = *ns.stats();
+        let mut changes = Vec::new();
+
+        // parents sort before their children so they are always made first
+        for (path, node) in ns.iter() {
+            let target = mpt.join(path);
+            let entry = unsafe { log.get_entry_ref(node.index as usize) };
+            let (_, mode, uid, gid) = node_attrs(log, node);
+
+            match entry.get_entry_type() {
+                LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => {
+                    if target.is_file() {
+                        stats.f_existed += 1;
+                        continue;
                     }
+                    if !dry_run && mount_file(&target, &file_meta.with_attrs(mode, uid, gid)).is_err() {
+                        stats.f_errs += 1;
+                        continue;
+                    }
+                    stats.f_created += 1;
+                    changes.push(MountChange::Create(path.to_path_buf()));
                 },
-                _ => continue
+                LogEntry::MakeDir { dir_meta: _ } => {
+                    if target.is_dir() {
+                        stats.d_existed += 1;
+                        continue;
+                    }
+                    if !dry_run && mount_dir(&target, mode, uid, gid).is_err() {
+                        stats.d_errs += 1;
+                        continue;
+                    }
+                    stats.d_created += 1;
+                    changes.push(MountChange::Mkdir(path.to_path_buf()));
+                },
+                // counted with the files, targets from the famfs root are
+                // pointed at the mount point
+                LogEntry::Symlink { symlink } => {
+                    if target.symlink_metadata().is_ok() {
+                        stats.f_existed += 1;
+                        continue;
+                    }
+                    let link = match symlink.target().strip_prefix("/") {
+                        Ok(from_root) => mpt.join(from_root),
+                        Err(_) => symlink.target().to_path_buf(),
+                    };
+                    if !dry_run && std::os::unix::fs::symlink(&link, &target).is_err() {
+                        stats.f_errs += 1;
+                        continue;
+                    }
+                    stats.f_created += 1;
+                    changes.push(MountChange::Symlink(path.to_path_buf()));
+                },
+                _ => {},
             }
         }
 
-        None
+        Ok((stats, changes))
+    }
+
+    // Bytes the allocator counts as in use, the superblock and log included
+    pub fn allocated_bytes(&self) -> u64 {
+        self.bitmap().alloc_sum()
+    }
+
+    // One digit per allocation unit, 1 for the ones in use
+    pub fn bitmap_string(&self) -> String {
+        self.bitmap().to_string()
+    }
+}
+
+// What logplay_mount did (or would do) to the mount point, paths are
+// relative to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountChange {
+    Mkdir(PathBuf),
+    Create(PathBuf),
+    Symlink(PathBuf)
+}
+
+// Removes whatever is under dir in the shadow but not in the namespace,
+// or is there as a file where the namespace has a directory or the other
+// way round. Paths that can't be removed count as errors.
+fn prune_shadow(ns: &Namespace, shadow_root: &Path, dir: &Path, stats: &mut famfs_log_stats) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(shadow_path(shadow_root, dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+
+        match ns.lookup(&path).map(|node| node.kind) {
+            Some(NodeKind::Dir) if is_dir => prune_shadow(ns, shadow_root, &path, stats)?,
+            Some(NodeKind::File) if !is_dir => {},
+            _ if is_dir => {
+                if std::fs::remove_dir_all(entry.path()).is_err() {
+                    stats.d_errs += 1;
+                }
+            },
+            _ => {
+                if std::fs::remove_file(entry.path()).is_err() {
+                    stats.f_errs += 1;
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+// Only chown when it changes something, so unprivileged users can play
+// logs of their own files
+fn mount_chown(path: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    let metadata = std::fs::metadata(path)?;
+    if metadata.uid() == uid && metadata.gid() == gid {
+        return Ok(());
+    }
+
+    std::os::unix::fs::chown(path, Some(uid), Some(gid))
+}
+
+fn mount_dir(path: &Path, mode: u32, uid: u32, gid: u32) -> std::io::Result<()> {
+    std::fs::DirBuilder::new().mode(mode).create(path)?;
+    // the umask may have taken bits off
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    mount_chown(path, uid, gid)
+}
+
+fn mount_file(path: &Path, file_meta: &famfs_log_file_meta) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(file_meta.fm_mode)
+        .open(path)?;
+    file.set_len(file_meta.fm_size)?;
+    file.set_permissions(std::fs::Permissions::from_mode(file_meta.fm_mode))?;
+    mount_chown(path, file_meta.fm_uid, file_meta.fm_gid)
+}
+
+impl Drop for famfs_locked_log<'_> {
+    fn drop(&mut self) {
+        // closing the fd would drop the lock too, but be explicit about it
+        let _ = self.lfd.unlock();
     }
+}
+
+// Finds path in ns, which indexes the log at logp, and maps it. The device
+// is expected to be mapped contiguously starting with the superblock. The
+// file is only writable if its flags allow it and read_only isn't asked for.
+pub(crate) unsafe fn get_file<'a>(
+    logp: *mut famfs_log,
+    open: &'a OpenFiles,
+    ns: &Namespace,
+    path: &Path,
+    read_only: bool,
+    persistence: Persistence
+) -> Option<FamfsFile<'a>> {
+    let path = ns.resolve(unsafe { logp.as_ref().unwrap() }, path, true).ok()?;
+    let node = ns.lookup(&path)?;
 
-    pub fn print_bitmap(&self) {
-        for i in 0..self.bitmap().len() {
-            print!("{}", if self.bitmap().test(i) {1} else {0});
+    unsafe { map_file(logp, open, node.index, !read_only, ns.coherence(), persistence) }.ok()
+}
+
+/// Maps the file logged at index through `open`, the mapping has to live
+/// as long as `open`. Fails with -EINVAL if the entry isn't a file,
+/// -EOPNOTSUPP if it has extents on another device, -EIO if its extents
+/// are shorter than the file or stray out of the device's data area and
+/// -EBUSY if another handle is in the way. Handles are kept apart by the
+/// bytes they map, so files a damaged log gave the same space exclude
+/// each other too.
+pub(crate) unsafe fn map_file<'a>(
+    logp: *mut famfs_log,
+    open: &'a OpenFiles,
+    index: u64,
+    write: bool,
+    coherence: Coherence,
+    persistence: Persistence
+) -> Result<FamfsFile<'a>, i64> {
+    let log = unsafe { logp.as_ref().unwrap() };
+    let file_meta = match unsafe { log.get_entry_ref(index as usize) }.get_entry_type() {
+        LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => file_meta,
+        _ => return Err(-(libc::EINVAL as i64)),
+    };
+
+    let base = unsafe { logp.cast::<u8>().sub(FAMFS_SUPERBLOCK_SIZE as usize) };
+    let sb = unsafe { base.cast::<famfs_superblock>().as_ref().unwrap() };
+    let data = FAMFS_SUPERBLOCK_SIZE + log.byte_len()..=sb.daxdev_size() as u64;
+
+    let mut segments = Vec::new();
+    for se in file_meta.segments() {
+        if se.se_devindex != 0 {
+            return Err(-(libc::EOPNOTSUPP as i64));
         }
-        println!();
+        let in_data = se.se_offset.checked_add(se.se_len).is_some_and(|end| data.contains(&se.se_offset) && data.contains(&end));
+        if !in_data {
+            return Err(-(libc::EIO as i64));
+        }
+        segments.push((unsafe { base.add(se.se_offset as usize) }, se.se_len as usize));
+    }
+    if segments.iter().map(|(_, len)| *len as u64).sum::<u64>() != file_meta.fm_size {
+        return Err(-(libc::EIO as i64));
     }
-}
\ No newline at end of file
+
+    let write = write && fm_flags_writable(file_meta.fm_flags);
+    unsafe { FamfsFile::new(open, segments, write, coherence, persistence) }.ok_or(-(libc::EBUSY as i64))
+}
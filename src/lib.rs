@@ -1,89 +1,447 @@
 pub mod meta;
 pub mod internal;
 pub mod bitmap;
+pub mod cache;
+pub mod namespace;
+pub mod fsck;
+pub mod logdump;
+pub mod shadow;
+pub mod relpath;
+pub mod access;
+pub mod metadata;
+pub mod dir;
+pub mod file;
 
-use std::{collections::HashMap, io::{Read, Write}, path::Path, ptr::NonNull};
-use meta::{famfs_superblock, famfs_log, FAMFS_MAX_PATHLEN};
+use std::{cell::{Ref, RefCell}, fs::{File, OpenOptions}, os::unix::fs::{FileTypeExt, MetadataExt}, path::{Path, PathBuf}, ptr::NonNull};
+use memmap2::{MmapMut, MmapOptions};
+use meta::{famfs_superblock, famfs_log, famfs_log_entry, LogEntry, FAMFS_LOG_LEN, FAMFS_LOG_OFFSET, MIN_DEVSIZE};
+use internal::famfs_locked_log;
+use namespace::{Namespace, NodeKind};
+use fsck::FsckReport;
+use logdump::LogDump;
+use access::{Credentials, ACCESS_READ, ACCESS_WRITE};
+use metadata::FamfsMetadata;
+use dir::{FamfsDirEntry, Walk};
+use file::OpenFiles;
+use cache::{Coherence, Persistence};
 
+pub use file::{FamfsFile, FamfsSegments, WriteMode};
 
-trait FamfsMetadataInterface {
-    fn superblock(&mut self) -> NonNull<famfs_superblock>;
 
-    fn log(&mut self) -> NonNull<famfs_log>;
+pub trait FamfsMetadataInterface {
+    fn superblock(&self) -> NonNull<famfs_superblock>;
 
-    fn commit(&mut self);
+    fn log(&self) -> NonNull<famfs_log>;
+
+    // How many bytes are mapped from the superblock on
+    fn mapped_len(&self) -> usize;
+
+    // Records a range of metadata that was modified and needs to be
+    // persisted by the next commit
+    fn mark_dirty(&mut self, range: DirtyPages);
+
+    // Opens a new file description on the backing store for flock'ing
+    fn lock_file(&self) -> std::io::Result<File>;
+
+    // Persists every range marked dirty since the last commit, the ranges
+    // are durable once this returns Ok. On an error the ranges that may
+    // not have made it stay dirty for the next commit to retry.
+    fn commit(&mut self) -> std::io::Result<()>;
+
+    fn flush_stats(&self) -> FlushStats;
+
+    // How file data written through the mapping is made durable, metadata
+    // goes through commit
+    fn persistence(&self) -> Persistence;
+}
+
+// (offset, len) of a modified range, offsets are relative to the start
+// of the superblock and of the log header respectively
+#[derive(Debug, Clone, Copy)]
+pub enum DirtyPages {
+    superblock(usize, usize),
+    log(usize, usize)
 }
 
-enum DirtyPages {
-    superblock(usize),
-    log(usize)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FlushStats {
+    pub commits: u64,
+    pub ranges_flushed: u64,
+    pub bytes_flushed: u64
 }
-struct MMAPed {
+
+// A famfs image file or dax device mapped in its entirety
+pub struct MMAPed {
+    map: MmapMut,
+    path: PathBuf,
+    dax: bool,
     superblock: NonNull<famfs_superblock>,
     log: NonNull<famfs_log>,
-    dirty_pages: Vec<DirtyPages>
+    dirty_pages: Vec<DirtyPages>,
+    flush_stats: FlushStats
+}
+
+impl MMAPed {
+    pub fn open(path: &Path) -> std::io::Result<MMAPed> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let metadata = file.metadata()?;
+        let dax = metadata.file_type().is_char_device();
+
+        let len = if dax {
+            Self::daxdev_size(metadata.rdev())?
+        } else {
+            metadata.len()
+        };
+
+        if len < FAMFS_LOG_OFFSET + size_of::<famfs_log>() as u64 {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+
+        let mut map = unsafe { MmapOptions::new().len(len as usize).map_mut(&file)? };
+        let base = map.as_mut_ptr();
+
+        Ok(MMAPed {
+            superblock: NonNull::new(base.cast::<famfs_superblock>()).unwrap(),
+            log: NonNull::new(unsafe { base.add(FAMFS_LOG_OFFSET as usize) }.cast::<famfs_log>()).unwrap(),
+            map,
+            path: path.to_path_buf(),
+            dax,
+            dirty_pages: Vec::new(),
+            flush_stats: FlushStats::default(),
+        })
+    }
+
+    // dax devices report a size of zero, the real size lives in sysfs
+    fn daxdev_size(rdev: u64) -> std::io::Result<u64> {
+        let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+        let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+        let size = std::fs::read_to_string(format!("/sys/dev/char/{major}:{minor}/size"))?;
+
+        size.trim().parse().map_err(|_| std::io::ErrorKind::InvalidData.into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn flush(&mut self, offset: usize, len: usize) -> std::io::Result<()> {
+        debug_assert!(offset + len <= self.map.len());
+
+        self.persistence().persist(unsafe { self.map.as_ptr().add(offset) }, len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Checks the image however damaged it is, unlike `Famfs::fsck` this
+    /// doesn't need an image `Famfs::open` accepts
+    pub fn fsck(&self) -> FsckReport {
+        let sb = unsafe { self.superblock.as_ref() };
+        let log = unsafe { self.log.as_ref() };
+        fsck::fsck(sb, log, self.map.len() as u64)
+    }
 }
 
-struct Famfs {
-    interface: Box<dyn FamfsMetadataInterface>
+impl FamfsMetadataInterface for MMAPed {
+    fn superblock(&self) -> NonNull<famfs_superblock> {
+        self.superblock
+    }
+
+    fn log(&self) -> NonNull<famfs_log> {
+        self.log
+    }
+
+    fn mapped_len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn mark_dirty(&mut self, range: DirtyPages) {
+        self.dirty_pages.push(range);
+    }
+
+    fn lock_file(&self) -> std::io::Result<File> {
+        File::open(&self.path)
+    }
+
+    fn commit(&mut self) -> std::io::Result<()> {
+        let mut ranges: Vec<(usize, usize)> = self.dirty_pages
+            .drain(..)
+            .map(|range| match range {
+                DirtyPages::superblock(offset, len) => (offset, offset + len),
+                DirtyPages::log(offset, len) => {
+                    let offset = FAMFS_LOG_OFFSET as usize + offset;
+                    (offset, offset + len)
+                }
+            })
+            .collect();
+        ranges.sort_unstable();
+
+        // coalesce overlapping and adjacent ranges so nothing is flushed twice
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = std::cmp::max(last.1, end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        for (i, &(start, end)) in merged.iter().enumerate() {
+            if let Err(err) = self.flush(start, end - start) {
+                // superblock offsets are from the start of the mapping too
+                self.dirty_pages.extend(merged[i..].iter().map(|&(start, end)| DirtyPages::superblock(start, end - start)));
+                return Err(err);
+            }
+            self.flush_stats.ranges_flushed += 1;
+            self.flush_stats.bytes_flushed += (end - start) as u64;
+        }
+        self.flush_stats.commits += 1;
+
+        Ok(())
+    }
+
+    fn flush_stats(&self) -> FlushStats {
+        self.flush_stats
+    }
+
+    fn persistence(&self) -> Persistence {
+        if self.dax { Persistence::WriteBack } else { Persistence::Msync }
+    }
+}
+
+pub struct Famfs {
+    interface: Box<dyn FamfsMetadataInterface>,
+    open: OpenFiles,
+    ns: RefCell<Namespace> // caught up with the log before each lookup
 }
 
 impl Famfs {
+    // Everything but fsck trusts the superblock and log, so interface has
+    // to map all the log and device they describe, see `open`
     fn new(interface: Box<dyn FamfsMetadataInterface>) -> Self {
         Self {
-            interface: interface
+            interface,
+            open: OpenFiles::default(),
+            ns: RefCell::new(Namespace::new()),
         }
     }
-}
 
-pub struct FamfsFile {
-    pub base: *mut u8,
-    pub len: usize,
-    pub cur: usize
-}
+    /// Creates a new famfs on an image file or dax device, the whole
+    /// file or device is used. Refuses to overwrite a valid famfs unless
+    /// `force` is set.
+    pub fn mkfs(path: &Path, force: bool) -> std::io::Result<Famfs> {
+        let mut mmaped = MMAPed::open(path)?;
+        if (mmaped.len() as u64) < MIN_DEVSIZE as u64 {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
 
-// todo make safe
-impl Read for FamfsFile {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.cur > self.len {
-            return Err(std::io::Error::from_raw_os_error(0));
+        let sb = unsafe { mmaped.superblock().as_mut() };
+        let log = unsafe { mmaped.log().as_mut() };
+        if !force && sb.check_superblock() {
+            return Err(std::io::ErrorKind::AlreadyExists.into());
         }
-        let bytes_read = std::cmp::min(buf.len(), self.len - self.cur);
-        let src_slice = unsafe {
-            std::slice::from_raw_parts(
-                self.base.add(self.cur),
-                bytes_read
-            )
-        };
 
-        buf[..bytes_read].copy_from_slice(src_slice);
-        
-        self.cur+=bytes_read;
-        Ok(bytes_read)
+        sb.format(mmaped.len() as u64, &mut mmaped);
+        log.format(FAMFS_LOG_LEN, &mut mmaped);
+        mmaped.commit()?;
+
+        Ok(Famfs::new(Box::new(mmaped)))
     }
-}
 
-// todo make safe (and correct)
-impl Write for FamfsFile {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if self.cur > self.len {
-            return Err(std::io::Error::from_raw_os_error(-1));
+    // Opens an image file or dax device holding a valid famfs
+    pub fn open(path: &Path) -> std::io::Result<Famfs> {
+        let mmaped = MMAPed::open(path)?;
+
+        let sb = unsafe { mmaped.superblock().as_ref() };
+        let log = unsafe { mmaped.log().as_ref() };
+        if !sb.check_superblock() || !log.check_log() {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        // files are mapped anywhere up to the size the superblock claims
+        if mmaped.len() < sb.daxdev_size() || (mmaped.len() as u64) < FAMFS_LOG_OFFSET + log.byte_len() {
+            return Err(std::io::ErrorKind::InvalidData.into());
         }
-        let bytes_written = std::cmp::min(buf.len(), self.len - self.cur);
-        let src_slice = unsafe {
-            std::slice::from_raw_parts_mut(
-                self.base.offset(self.cur as isize),
-                bytes_written
-            )
-        };
 
-        src_slice[..bytes_written].copy_from_slice(&buf[..bytes_written]);
+        Ok(Famfs::new(Box::new(mmaped)))
+    }
 
-        Ok(bytes_written)
+    pub fn flush_stats(&self) -> FlushStats {
+        self.interface.flush_stats()
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+    pub fn superblock(&mut self) -> &famfs_superblock {
+        unsafe { self.interface.superblock().as_ref() }
+    }
+
+    pub fn log(&mut self) -> &famfs_log {
+        unsafe { self.interface.log().as_ref() }
+    }
+
+    // Entries past the end of the log are None
+    pub fn log_entry(&mut self, index: u64) -> Option<&famfs_log_entry> {
+        let log = unsafe { self.interface.log().as_ref() };
+        if index >= log.len() || index > log.max_size() {
+            return None;
+        }
+
+        Some(unsafe { log.get_entry_ref(index as usize) })
+    }
+
+    pub fn coherence(&self) -> Coherence {
+        self.ns.borrow().coherence()
+    }
+
+    /// Says whether other hosts' stores to the memory show up in this
+    /// host's caches. On non coherent memory, the default, log replays
+    /// and reads through file handles drop the cached lines they cover
+    /// first. Handles opened earlier keep the coherence they were opened
+    /// with.
+    pub fn set_coherence(&mut self, coherence: Coherence) {
+        self.ns.borrow_mut().set_coherence(coherence);
+    }
+
+    // Only applies the entries appended since the last lookup
+    fn namespace(&self) -> Ref<'_, Namespace> {
+        unsafe { self.ns.borrow_mut().apply_new(self.interface.log().as_ptr()) };
+        self.ns.borrow()
+    }
+
+    // The namespace as the log is now
+    pub fn replay(&self) -> Namespace {
+        self.namespace().clone()
+    }
+
+    // None if nothing lives at path. Symlinks on the way are followed, a
+    // symlink at path is described itself, like lstat(2).
+    pub fn stat(&mut self, path: &Path) -> Option<FamfsMetadata> {
+        let ns = self.namespace();
+        let log = unsafe { self.interface.log().as_ref() };
+        let node = *ns.lookup(&ns.resolve(log, path, false).ok()?)?;
+        FamfsMetadata::from_node(log, &node)
+    }
+
+    /// The files and directories directly in `dir` (the empty path for the
+    /// root) in path order, symlinks to `dir` and on the way to it are
+    /// followed. The entries' paths are under `dir` as it was given.
+    /// Fails with -ENOENT, -ENOTDIR or -ELOOP.
+    pub fn read_dir(&mut self, dir: &Path) -> Result<Vec<FamfsDirEntry>, i64> {
+        let ns = self.namespace();
+        dir::read_dir(&ns, unsafe { self.interface.log().as_ref() }, dir)
+    }
+
+    /// The target of the symlink at `path`, as it was logged. Symlinks in
+    /// the directories on the way are followed. Fails with -ENOENT,
+    /// -ENOTDIR, -ELOOP or -EINVAL if `path` isn't a symlink.
+    pub fn read_link(&self, path: &Path) -> Result<PathBuf, i64> {
+        let ns = self.namespace();
+        let log = unsafe { self.interface.log().as_ref() };
+        let path = ns.resolve(log, path, false)?;
+        // the root has no node, and isn't a symlink either
+        let node = ns.lookup(&path).ok_or(-(libc::EINVAL as i64))?;
+
+        match unsafe { log.get_entry_ref(node.index as usize) }.get_entry_type() {
+            LogEntry::Symlink { symlink } => Ok(symlink.target().to_path_buf()),
+            _ => Err(-(libc::EINVAL as i64)),
+        }
     }
-}
 
+    /// The path `path` leads to once every symlink in it is followed,
+    /// see `Namespace::resolve`
+    pub fn canonicalize(&self, path: &Path) -> Result<PathBuf, i64> {
+        let ns = self.namespace();
+        ns.resolve(unsafe { self.interface.log().as_ref() }, path, true)
+    }
+
+    /// Walks everything below `dir` depth first, see `Walk` for filters.
+    /// `dir` is looked up like `read_dir` does, symlinks below it aren't
+    /// followed.
+    pub fn walk(&mut self, dir: &Path) -> Result<Walk, i64> {
+        let ns = self.namespace();
+        dir::walk(&ns, unsafe { self.interface.log().as_ref() }, dir)
+    }
+
+    // Writable if the file was logged RW, symlinks are followed. None if
+    // the file can't be mapped or another handle holds it for writing (or,
+    // for a writable handle, holds it at all).
+    pub fn get_file(&self, path: &Path) -> Option<FamfsFile<'_>> {
+        unsafe { internal::get_file(self.interface.log().as_ptr(), &self.open, &self.namespace(), path, false, self.interface.persistence()) }
+    }
+
+    // Never writable, whatever the file's flags say
+    pub fn get_file_read_only(&self, path: &Path) -> Option<FamfsFile<'_>> {
+        unsafe { internal::get_file(self.interface.log().as_ptr(), &self.open, &self.namespace(), path, true, self.interface.persistence()) }
+    }
+
+    /// Checks whether `creds` may access `path` with `mask` (ACCESS_*
+    /// bits), following symlinks and searching every directory on the way
+    /// like `open_file`. Fails with -ENOENT, -ENOTDIR, -ELOOP or -EACCES.
+    pub fn access(&self, path: &Path, creds: &Credentials, mask: u32) -> Result<(), i64> {
+        let ns = self.namespace();
+        let log = unsafe { self.interface.log().as_ref() };
+        let path = access::resolve_as(&ns, log, path, creds, true)?;
+        access::check_access(&ns, log, &path, creds, mask).map(|_| ())
+    }
+
+    /// Opens a file as `creds` would, following symlinks. Reading needs r
+    /// and writing w on the file, and every directory the lookup goes
+    /// through, including those holding the links, needs x. Writing a
+    /// file logged read only fails with -EROFS, opening a file another
+    /// handle is writing (or writing one another handle has open) with
+    /// -EBUSY.
+    pub fn open_file(&self, path: &Path, creds: &Credentials, write: bool) -> Result<FamfsFile<'_>, i64> {
+        let ns = self.namespace();
+        let logp = self.interface.log().as_ptr();
+        let log = unsafe { logp.as_ref().unwrap() };
+
+        let mask = if write { ACCESS_READ | ACCESS_WRITE } else { ACCESS_READ };
+        let path = access::resolve_as(&ns, log, path, creds, true)?;
+        let node = access::check_access(&ns, log, &path, creds, mask)?;
+
+        if node.kind == NodeKind::Dir {
+            return Err(-(libc::EISDIR as i64));
+        }
+        let file = unsafe { internal::map_file(logp, &self.open, node.index, write, ns.coherence(), self.interface.persistence()) }?;
+        if write && !file.is_writable() {
+            return Err(-(libc::EROFS as i64));
+        }
+
+        Ok(file)
+    }
+
+    /// Logs new permission bits for `path`, see
+    /// `famfs_locked_log::set_permissions`. Waits for the log lock, a
+    /// failure to take it comes back as its errno.
+    pub fn set_permissions(&mut self, path: &Path, mode: u32) -> Result<(), i64> {
+        let mut log = self.lock_log().map_err(|err| -(err.raw_os_error().unwrap_or(libc::EIO) as i64))?;
+        log.set_permissions(path, mode)
+    }
+
+    /// Logs a new owner and/or group for `path` like `set_permissions`
+    pub fn set_owner(&mut self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(), i64> {
+        let mut log = self.lock_log().map_err(|err| -(err.raw_os_error().unwrap_or(libc::EIO) as i64))?;
+        log.set_owner(path, uid, gid)
+    }
+
+    pub fn fsck(&mut self) -> FsckReport {
+        let sb = unsafe { self.interface.superblock().as_ref() };
+        let log = unsafe { self.interface.log().as_ref() };
+        fsck::fsck(sb, log, self.interface.mapped_len() as u64)
+    }
+
+    pub fn dump_log(&mut self) -> LogDump {
+        unsafe { LogDump::new(self.interface.log().as_ref()) }
+    }
+
+    /// Takes the log for mutation, waits for any other holder to drop it
+    pub fn lock_log(&mut self) -> std::io::Result<famfs_locked_log<'_>> {
+        let lfd = self.interface.lock_file()?;
+        unsafe { famfs_locked_log::from_log(self.interface.as_mut(), lfd) }
+    }
+
+    /// Takes the log for mutation, fails with `ErrorKind::WouldBlock`
+    /// if someone else holds it
+    pub fn try_lock_log(&mut self) -> std::io::Result<famfs_locked_log<'_>> {
+        let lfd = self.interface.lock_file()?;
+        unsafe { famfs_locked_log::try_from_log(self.interface.as_mut(), lfd) }
+    }
+}
@@ -0,0 +1,360 @@
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+
+use crate::meta::{
+    famfs_log, famfs_log_entry, famfs_log_entry_type, famfs_simple_extent, Extent, LogEntry,
+    FAMFS_MAX_INTERLEAVED_EXTENTS, FAMFS_MAX_SIMPLE_EXTENTS,
+};
+use crate::namespace::relpath_to_path;
+
+// What a log dump shows of an interleaved extent
+#[derive(Debug, Clone)]
+pub struct InterleavedDump {
+    pub chunk_size: u64,
+    pub strips: Vec<famfs_simple_extent>
+}
+
+#[derive(Debug, Clone)]
+pub enum ExtentDump {
+    Simple(Vec<famfs_simple_extent>),
+    Interleaved(Vec<InterleavedDump>)
+}
+
+// The metadata an entry carries, directories, symlinks and setattrs have
+// no size, flags or extents so those are None for them. Only symlinks
+// have a target.
+#[derive(Debug, Clone)]
+pub struct EntryMetaDump {
+    pub path: PathBuf,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub size: Option<u64>,
+    pub flags: Option<u32>,
+    pub extents: Option<ExtentDump>,
+    pub target: Option<PathBuf>
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameDump {
+    pub from: PathBuf,
+    pub to: PathBuf
+}
+
+#[derive(Debug, Clone)]
+pub struct EntryDump {
+    pub index: u64,
+    pub seqnum: u64,
+    pub raw_type: u32,
+    pub entry_type: famfs_log_entry_type,
+    pub meta: Option<EntryMetaDump>, // None for renames and invalid entries
+    pub rename: Option<RenameDump>,
+    pub crc: u32,
+    pub crc_ok: bool
+}
+
+#[derive(Debug, Clone)]
+pub struct LogDump {
+    pub magic: u64,
+    pub len: u64,
+    pub last_index: u64,
+    pub next_index: u64,
+    pub next_seqnum: u64,
+    pub crc_ok: bool,
+    pub entries: Vec<EntryDump>
+}
+
+fn extent_dump(extent: Extent) -> ExtentDump {
+    // counts come straight off the media, clamp them like extent_list does
+    match extent {
+        Extent::Simple { extent } => {
+            let nextents = std::cmp::min(extent.fmap_nextents as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+            ExtentDump::Simple(extent.se[..nextents].to_vec())
+        },
+        Extent::Interleaved { extent } => {
+            let niext = std::cmp::min(extent.fmap_niext as usize, FAMFS_MAX_INTERLEAVED_EXTENTS);
+            ExtentDump::Interleaved(extent.se[..niext]
+                .iter()
+                .map(|ie| InterleavedDump {
+                    chunk_size: ie.ie_chunk_size,
+                    strips: ie.ie_strips[..std::cmp::min(ie.ie_nstrips as usize, FAMFS_MAX_SIMPLE_EXTENTS)].to_vec(),
+                })
+                .collect())
+        },
+    }
+}
+
+impl EntryDump {
+    pub fn new(index: u64, entry: &famfs_log_entry) -> EntryDump {
+        let mut rename = None;
+        let meta = match entry.get_entry_type() {
+            LogEntry::File { file_meta } | LogEntry::Delete { file_meta } | LogEntry::Resize { file_meta } => Some(EntryMetaDump {
+                path: relpath_to_path(&file_meta.fm_relpath),
+                uid: file_meta.fm_uid,
+                gid: file_meta.fm_gid,
+                mode: file_meta.fm_mode,
+                size: Some(file_meta.fm_size),
+                flags: Some(file_meta.fm_flags),
+                extents: Some(extent_dump(file_meta.get_extent())),
+                target: None,
+            }),
+            LogEntry::MakeDir { dir_meta } => Some(EntryMetaDump {
+                path: relpath_to_path(&dir_meta.md_relpath),
+                uid: dir_meta.uid(),
+                gid: dir_meta.gid(),
+                mode: dir_meta.mode(),
+                size: None,
+                flags: None,
+                extents: None,
+                target: None,
+            }),
+            LogEntry::SetAttr { attr } => Some(EntryMetaDump {
+                path: relpath_to_path(&attr.sa_relpath),
+                uid: attr.uid(),
+                gid: attr.gid(),
+                mode: attr.mode(),
+                size: None,
+                flags: None,
+                extents: None,
+                target: None,
+            }),
+            LogEntry::Symlink { symlink } => Some(EntryMetaDump {
+                path: relpath_to_path(&symlink.sl_relpath),
+                uid: symlink.uid(),
+                gid: symlink.gid(),
+                mode: 0o777,
+                size: None,
+                flags: None,
+                extents: None,
+                target: Some(symlink.target().to_path_buf()),
+            }),
+            LogEntry::Rename { rename: rn } => {
+                rename = Some(RenameDump {
+                    from: relpath_to_path(&rn.rn_relpath),
+                    to: relpath_to_path(&rn.rn_new_relpath),
+                });
+                None
+            },
+            LogEntry::Invalid => None,
+        };
+
+        EntryDump {
+            index,
+            seqnum: entry.seqnum(),
+            raw_type: entry.raw_type(),
+            entry_type: entry.entry_type(),
+            meta,
+            rename,
+            crc: entry.crc(),
+            crc_ok: entry.check_crc(),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self.entry_type {
+            famfs_log_entry_type::FAMFS_LOG_FILE => "file",
+            famfs_log_entry_type::FAMFS_LOG_MKDIR => "mkdir",
+            famfs_log_entry_type::FAMFS_LOG_DELETE => "delete",
+            famfs_log_entry_type::FAMFS_LOG_RENAME => "rename",
+            famfs_log_entry_type::FAMFS_LOG_SETATTR => "setattr",
+            famfs_log_entry_type::FAMFS_LOG_SYMLINK => "symlink",
+            famfs_log_entry_type::FAMFS_LOG_RESIZE => "resize",
+            famfs_log_entry_type::FAMFS_LOG_INVALID => "invalid",
+        }
+    }
+}
+
+impl LogDump {
+    /// Dumps the header and every entry of a log. A header without the
+    /// log magic is dumped without entries since its index can't be
+    /// trusted, otherwise the entries are bounded by the log length.
+    ///
+    /// # Safety
+    /// `log` must be followed by the `famfs_log_len` bytes of its entries
+    pub unsafe fn new(log: &famfs_log) -> LogDump {
+        let mut entries = Vec::new();
+
+        if log.check_log() {
+            let capacity = famfs_log::entry_count(log.byte_len());
+            let count = std::cmp::min(log.len(), capacity);
+
+            for i in 0..count {
+                entries.push(EntryDump::new(i, unsafe { log.get_entry_ref(i as usize) }));
+            }
+        }
+
+        LogDump {
+            magic: log.famfs_log_magic,
+            len: log.famfs_log_len,
+            last_index: log.famfs_log_last_index,
+            next_index: log.famfs_log_next_index,
+            next_seqnum: log.famfs_log_next_seqnum,
+            crc_ok: log.check_crc(),
+            entries,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+
+        write!(
+            json,
+            "{{\"magic\":{},\"len\":{},\"last_index\":{},\"next_index\":{},\"next_seqnum\":{},\"crc_ok\":{},\"entries\":[",
+            self.magic, self.len, self.last_index, self.next_index, self.next_seqnum, self.crc_ok
+        ).unwrap();
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            entry_json(&mut json, entry);
+        }
+        json.push_str("]}");
+
+        json
+    }
+}
+
+fn entry_json(json: &mut String, entry: &EntryDump) {
+    write!(
+        json,
+        "{{\"index\":{},\"seqnum\":{},\"type\":\"{}\",\"raw_type\":{}",
+        entry.index, entry.seqnum, entry.type_name(), entry.raw_type
+    ).unwrap();
+
+    if let Some(rename) = &entry.rename {
+        json.push_str(",\"path\":");
+        json_string(json, &rename.from.to_string_lossy());
+        json.push_str(",\"new_path\":");
+        json_string(json, &rename.to.to_string_lossy());
+    }
+
+    if let Some(meta) = &entry.meta {
+        json.push_str(",\"path\":");
+        json_string(json, &meta.path.to_string_lossy());
+        write!(json, ",\"uid\":{},\"gid\":{},\"mode\":{}", meta.uid, meta.gid, meta.mode).unwrap();
+
+        if let Some(size) = meta.size {
+            write!(json, ",\"size\":{size}").unwrap();
+        }
+        if let Some(flags) = meta.flags {
+            write!(json, ",\"flags\":{flags}").unwrap();
+        }
+        if let Some(target) = &meta.target {
+            json.push_str(",\"target\":");
+            json_string(json, &target.to_string_lossy());
+        }
+
+        match &meta.extents {
+            Some(ExtentDump::Simple(extents)) => {
+                json.push_str(",\"ext_type\":\"simple\",\"extents\":");
+                extents_json(json, extents);
+            },
+            Some(ExtentDump::Interleaved(interleaved)) => {
+                json.push_str(",\"ext_type\":\"interleaved\",\"extents\":[");
+                for (i, ie) in interleaved.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    write!(json, "{{\"chunk_size\":{},\"strips\":", ie.chunk_size).unwrap();
+                    extents_json(json, &ie.strips);
+                    json.push('}');
+                }
+                json.push(']');
+            },
+            None => {},
+        }
+    }
+
+    write!(json, ",\"crc\":{},\"crc_ok\":{}}}", entry.crc, entry.crc_ok).unwrap();
+}
+
+fn extents_json(json: &mut String, extents: &[famfs_simple_extent]) {
+    json.push('[');
+    for (i, se) in extents.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write!(json, "{{\"devindex\":{},\"offset\":{},\"len\":{}}}", se.se_devindex, se.se_offset, se.se_len).unwrap();
+    }
+    json.push(']');
+}
+
+fn json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+impl fmt::Display for EntryDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>6} seqnum {:<6} {:<7}", self.index, self.seqnum, self.type_name())?;
+        if self.entry_type == famfs_log_entry_type::FAMFS_LOG_INVALID {
+            write!(f, " (raw type {})", self.raw_type)?;
+        }
+        if !self.crc_ok {
+            write!(f, " BAD CRC {:#010x}", self.crc)?;
+        }
+        writeln!(f)?;
+
+        if let Some(rename) = &self.rename {
+            writeln!(f, "        path:  {} -> {}", rename.from.display(), rename.to.display())?;
+        }
+
+        let Some(meta) = &self.meta else { return Ok(()) };
+        match &meta.target {
+            Some(target) => writeln!(f, "        path:  {} -> {}", meta.path.display(), target.display())?,
+            None => writeln!(f, "        path:  {}", meta.path.display())?,
+        }
+        writeln!(f, "        uid {} gid {} mode {:04o}", meta.uid, meta.gid, meta.mode)?;
+        if let (Some(size), Some(flags)) = (meta.size, meta.flags) {
+            writeln!(f, "        size {size} flags {flags:#x}")?;
+        }
+
+        match &meta.extents {
+            Some(ExtentDump::Simple(extents)) => {
+                for se in extents {
+                    writeln!(f, "        extent: dev {} offset {:#x} len {:#x}", se.se_devindex, se.se_offset, se.se_len)?;
+                }
+            },
+            Some(ExtentDump::Interleaved(interleaved)) => {
+                for ie in interleaved {
+                    writeln!(f, "        interleaved: {} strips chunk {:#x}", ie.strips.len(), ie.chunk_size)?;
+                    for se in &ie.strips {
+                        writeln!(f, "          strip: dev {} offset {:#x} len {:#x}", se.se_devindex, se.se_offset, se.se_len)?;
+                    }
+                }
+            },
+            None => {},
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "magic:       {:#x}", self.magic)?;
+        writeln!(f, "len:         {}", self.len)?;
+        writeln!(f, "last index:  {}", self.last_index)?;
+        writeln!(f, "next index:  {}", self.next_index)?;
+        writeln!(f, "next seqnum: {}", self.next_seqnum)?;
+        writeln!(f, "crc:         {}", if self.crc_ok { "ok" } else { "bad" })?;
+
+        for entry in &self.entries {
+            write!(f, "{entry}")?;
+        }
+
+        Ok(())
+    }
+}
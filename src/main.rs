@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use famfs_rs::{Famfs, WriteMode};
+use famfs_rs::access::Credentials;
+use famfs_rs::internal::MountChange;
+use famfs_rs::meta::{fm_flags_writable, FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+use famfs_rs::metadata::FamfsMetadata;
+use famfs_rs::namespace::NodeKind;
+use famfs_rs::relpath::RelPath;
+
+const USAGE: &str = "\
+usage: famfs-rs <command> [options] DEVICE [args]
+
+DEVICE is a famfs image file or dax device. Paths within famfs are
+relative to its root, cp marks them with a famfs: prefix.
+
+commands:
+  mkfs [-f] DEVICE                          create a famfs (-f overwrites one)
+  ls [-l] DEVICE [PATH]                     list a directory
+  find [-t f|d|l] [-s MIN] [-S MAX] DEVICE [PATH]
+                                            list everything below a directory, depth first
+                                            (-t only files, directories or symlinks, -s and -S only
+                                            files of at least MIN or at most MAX bytes)
+  stat DEVICE PATH                          show a file, directory or symlink
+  creat -s SIZE [-r] [-m MODE] [-u UID] [-g GID] DEVICE PATH
+                                            create a file of SIZE bytes (K, M and G suffixes),
+                                            -r makes it read only on all hosts
+  mkdir [-p] [-m MODE] [-u UID] [-g GID] DEVICE PATH
+                                            create a directory (-p creates parents)
+  cp DEVICE SRC DST                         copy a file in (DST=famfs:PATH) or out (SRC=famfs:PATH)
+  cat DEVICE PATH                           write a file to stdout
+  ln -s DEVICE TARGET PATH                  create a symlink to TARGET (a leading / is the famfs root)
+  readlink DEVICE PATH                      print the target of a symlink
+  rm DEVICE PATH                            remove a file or symlink
+  mv DEVICE SRC DST                         rename a file or directory, DST must not exist
+  chmod DEVICE MODE PATH                    set the permission bits (octal) of a file or directory
+  chown DEVICE [UID][:GID] PATH             set the owner and/or group of a file or directory
+  truncate -s SIZE DEVICE PATH              set the size of a file, growing it allocates more space
+  fsck DEVICE                               check the superblock and log
+  bitmap DEVICE                             print the allocation bitmap, a 1 for each allocation
+                                            unit in use
+  logplay [-v] [-s SHADOW] [-m MPT [-n]] DEVICE
+                                            replay the log and report what it holds (-v lists it,
+                                            -s plays it into a shadow yaml tree at SHADOW, -m creates
+                                            its files and directories under MPT, -n only says what
+                                            -m would create)
+  logdump [-j] DEVICE                       print every log entry (-j as json)
+";
+
+const FAMFS_PREFIX: &str = "famfs:";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match run(&args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("famfs-rs: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    let Some((cmd, args)) = args.split_first() else {
+        eprint!("{USAGE}");
+        return Ok(ExitCode::FAILURE);
+    };
+
+    match cmd.as_str() {
+        "mkfs" => mkfs(args),
+        "ls" => ls(args),
+        "find" => find(args),
+        "stat" => stat(args),
+        "creat" => creat(args),
+        "mkdir" => mkdir(args),
+        "cp" => cp(args),
+        "cat" => cat(args),
+        "rm" => rm(args),
+        "mv" => mv(args),
+        "ln" => ln(args),
+        "readlink" => readlink(args),
+        "chmod" => chmod(args),
+        "chown" => chown(args),
+        "truncate" => truncate(args),
+        "fsck" => fsck(args),
+        "bitmap" => bitmap(args),
+        "logplay" => logplay(args),
+        "logdump" => logdump(args),
+        "help" | "-h" | "--help" => {
+            print!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        },
+        _ => Err(format!("unknown command '{cmd}'\n{USAGE}")),
+    }
+}
+
+struct Opts {
+    opts: HashMap<char, String>,
+    args: Vec<String>
+}
+
+impl Opts {
+    // Splits args into single letter options and positional arguments,
+    // options in `with_value` take the following argument as their value
+    fn parse(args: &[String], switches: &str, with_value: &str, nargs: std::ops::RangeInclusive<usize>) -> Result<Opts, String> {
+        let mut opts = HashMap::new();
+        let mut positional = Vec::new();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            let Some(name) = arg.strip_prefix('-').filter(|name| name.len() == 1) else {
+                positional.push(arg.clone());
+                continue;
+            };
+            let name = name.chars().next().unwrap();
+
+            if switches.contains(name) {
+                opts.insert(name, String::new());
+            } else if with_value.contains(name) {
+                let value = iter.next().ok_or(format!("-{name} needs a value"))?;
+                opts.insert(name, value.clone());
+            } else {
+                return Err(format!("unknown option -{name}"));
+            }
+        }
+
+        if !nargs.contains(&positional.len()) {
+            return Err(format!("wrong number of arguments\n{USAGE}"));
+        }
+
+        Ok(Opts { opts, args: positional })
+    }
+
+    fn has(&self, name: char) -> bool {
+        self.opts.contains_key(&name)
+    }
+
+    fn num(&self, name: char, radix: u32, default: u32) -> Result<u32, String> {
+        match self.opts.get(&name) {
+            Some(value) => u32::from_str_radix(value, radix).map_err(|_| format!("bad value for -{name}: {value}")),
+            None => Ok(default),
+        }
+    }
+
+    // mode, uid and gid of new files and directories
+    fn owner(&self, default_mode: u32) -> Result<(u32, u32, u32), String> {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok((self.num('m', 8, default_mode)?, self.num('u', 10, uid)?, self.num('g', 10, gid)?))
+    }
+}
+
+fn open(device: &str) -> Result<Famfs, String> {
+    Famfs::open(Path::new(device)).map_err(|err| format!("{device}: {err}"))
+}
+
+// famfs paths are relative to the root, a leading / is tolerated and
+// an empty path is the root itself
+fn famfs_path(path: &str) -> Result<PathBuf, String> {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return Ok(PathBuf::new());
+    }
+
+    RelPath::new(Path::new(path))
+        .map(|relpath| relpath.as_path().to_path_buf())
+        .map_err(|err| format!("{path}: {err}"))
+}
+
+// Reads and writes of existing files are checked against the caller
+fn credentials() -> Result<Credentials, String> {
+    Credentials::current().map_err(|err| format!("credentials: {err}"))
+}
+
+fn log_error(path: &Path, err: i64) -> String {
+    if err < 0 {
+        format!("{}: {}", path.display(), std::io::Error::from_raw_os_error(-err as i32))
+    } else {
+        format!("{}: log full", path.display())
+    }
+}
+
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, shift) = match size.chars().last() {
+        Some('k' | 'K') => (&size[..size.len() - 1], 10),
+        Some('m' | 'M') => (&size[..size.len() - 1], 20),
+        Some('g' | 'G') => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or(format!("bad size {size}"))
+}
+
+fn mode_string(metadata: &FamfsMetadata) -> String {
+    let mut s = String::from(match metadata.kind() {
+        NodeKind::File => "-",
+        NodeKind::Dir => "d",
+        NodeKind::Symlink => "l",
+    });
+    for shift in [6, 3, 0] {
+        let bits = (metadata.mode() >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+
+fn mkfs(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "f", "", 1..=1)?;
+    let device = &opts.args[0];
+
+    let mut fs = Famfs::mkfs(Path::new(device), opts.has('f')).map_err(|err| match err.kind() {
+        std::io::ErrorKind::AlreadyExists => format!("{device}: already holds a famfs, use -f to overwrite it"),
+        _ => format!("{device}: {err}"),
+    })?;
+
+    let sb = fs.superblock();
+    println!("famfs created on {device}");
+    println!("uuid:       {}", sb.uuid());
+    println!("size:       {}", sb.daxdev_size());
+    println!("alloc unit: {}", sb.alloc_unit());
+    println!("log:        {} bytes at {:#x}", sb.log_len(), sb.log_offset());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn ls(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "l", "", 1..=2)?;
+    let mut fs = open(&opts.args[0])?;
+    let dir = famfs_path(opts.args.get(1).map(String::as_str).unwrap_or(""))?;
+
+    let listing: Vec<(PathBuf, FamfsMetadata)> = match fs.read_dir(&dir) {
+        Ok(entries) => entries.into_iter().map(|entry| (entry.path().to_path_buf(), entry.metadata().clone())).collect(),
+        Err(err) if err == -(libc::ENOTDIR as i64) => vec![(dir.clone(), fs.stat(&dir).unwrap())],
+        Err(err) => return Err(log_error(&dir, err)),
+    };
+
+    for (path, metadata) in listing {
+        let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+        if !opts.has('l') {
+            println!("{name}");
+            continue;
+        }
+
+        let target = match metadata.is_symlink() {
+            true => format!(" -> {}", fs.read_link(&path).map_err(|err| log_error(&path, err))?.display()),
+            false => String::new(),
+        };
+        println!(
+            "{} {:>6} {:>6} {:>12} {name}{target}",
+            mode_string(&metadata), metadata.uid(), metadata.gid(), metadata.len()
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn find(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "tsS", 1..=2)?;
+    let mut fs = open(&opts.args[0])?;
+    let dir = famfs_path(opts.args.get(1).map(String::as_str).unwrap_or(""))?;
+
+    let mut walk = fs.walk(&dir).map_err(|err| log_error(&dir, err))?;
+    match opts.opts.get(&'t').map(String::as_str) {
+        Some("f") => walk = walk.files(),
+        Some("d") => walk = walk.dirs(),
+        Some("l") => walk = walk.kind(NodeKind::Symlink),
+        Some(kind) => return Err(format!("bad value for -t: {kind}")),
+        None => {},
+    }
+    if let Some(size) = opts.opts.get(&'s') {
+        walk = walk.min_len(parse_size(size)?);
+    }
+    if let Some(size) = opts.opts.get(&'S') {
+        walk = walk.max_len(parse_size(size)?);
+    }
+
+    for entry in walk {
+        println!("{}", entry.path().display());
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn stat(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 2..=2)?;
+    let mut fs = open(&opts.args[0])?;
+    let path = famfs_path(&opts.args[1])?;
+
+    let metadata = fs.stat(&path).ok_or(format!("{}: no such file or directory", path.display()))?;
+
+    println!("  path: {}", path.display());
+    let kind = match metadata.kind() {
+        NodeKind::File => "file",
+        NodeKind::Dir => "directory",
+        NodeKind::Symlink => "symlink",
+    };
+    println!("  type: {kind}");
+    if metadata.is_symlink() {
+        println!("target: {}", fs.read_link(&path).map_err(|err| log_error(&path, err))?.display());
+    }
+    println!("  size: {}", metadata.len());
+    println!("  mode: {:04o} ({})", metadata.mode(), mode_string(&metadata));
+    println!("   uid: {}", metadata.uid());
+    println!("   gid: {}", metadata.gid());
+    println!("seqnum: {}", metadata.seqnum());
+
+    if metadata.is_file() {
+        let access = if fm_flags_writable(metadata.flags()) { "read-write" } else { "read-only" };
+        println!(" flags: {:#x} ({access})", metadata.flags());
+        for (i, extent) in metadata.extents().iter().enumerate() {
+            println!("extent: {i} offset {:#x} len {:#x}", extent.se_offset, extent.se_len);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn creat(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "r", "smug", 2..=2)?;
+    let size = parse_size(opts.opts.get(&'s').ok_or("creat needs -s SIZE")?)?;
+    let (mode, uid, gid) = opts.owner(0o644)?;
+    let path = famfs_path(&opts.args[1])?;
+
+    let mut fs = open(&opts.args[0])?;
+    let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+    let flags = if opts.has('r') { FAMFS_FM_ALL_HOSTS_RO } else { FAMFS_FM_ALL_HOSTS_RW };
+    log.make_file(&path, mode, uid, gid, size, flags).map_err(|err| log_error(&path, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn mkdir(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "p", "mug", 2..=2)?;
+    let (mode, uid, gid) = opts.owner(0o755)?;
+    let path = famfs_path(&opts.args[1])?;
+
+    let mut fs = open(&opts.args[0])?;
+    let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+    let ns = log.namespace();
+
+    let dirs: Vec<&Path> = if opts.has('p') {
+        // every missing ancestor, outermost first
+        let mut missing: Vec<&Path> = path.ancestors()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .take_while(|dir| ns.lookup(dir).is_none())
+            .collect();
+        missing.reverse();
+        missing
+    } else {
+        vec![path.as_path()]
+    };
+
+    for dir in dirs {
+        log.make_dir(dir, mode, uid, gid).map_err(|err| log_error(dir, err))?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn cp(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 3..=3)?;
+    let mut fs = open(&opts.args[0])?;
+    let (src, dst) = (&opts.args[1], &opts.args[2]);
+
+    match (src.strip_prefix(FAMFS_PREFIX), dst.strip_prefix(FAMFS_PREFIX)) {
+        (None, Some(dst)) => {
+            let dst = famfs_path(dst)?;
+            let mut input = File::open(src).map_err(|err| format!("{src}: {err}"))?;
+            let metadata = input.metadata().map_err(|err| format!("{src}: {err}"))?;
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+            {
+                let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+                let mode = metadata.permissions().mode() & 0o777;
+                log.make_file(&dst, mode, uid, gid, metadata.len(), FAMFS_FM_ALL_HOSTS_RW).map_err(|err| log_error(&dst, err))?;
+            }
+
+            // nothing reads the copy back soon, keep it out of the cache
+            let mut output = fs.get_file(&dst).ok_or(format!("{}: lost after creating it", dst.display()))?;
+            output.set_write_mode(WriteMode::NonTemporal);
+            std::io::copy(&mut input, &mut output).map_err(|err| format!("{}: {err}", dst.display()))?;
+            output.flush().map_err(|err| format!("{}: {err}", dst.display()))?;
+        },
+        (Some(src), None) => {
+            let src = famfs_path(src)?;
+            let mut input = fs.open_file(&src, &credentials()?, false).map_err(|err| log_error(&src, err))?;
+            let mut output = File::create(dst).map_err(|err| format!("{dst}: {err}"))?;
+            std::io::copy(&mut input, &mut output).map_err(|err| format!("{dst}: {err}"))?;
+        },
+        _ => return Err(format!("exactly one of SRC and DST needs the {FAMFS_PREFIX} prefix")),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn cat(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 2..=2)?;
+    let fs = open(&opts.args[0])?;
+    let path = famfs_path(&opts.args[1])?;
+
+    let mut file = fs.open_file(&path, &credentials()?, false).map_err(|err| log_error(&path, err))?;
+    std::io::copy(&mut file, &mut std::io::stdout().lock()).map_err(|err| format!("{}: {err}", path.display()))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn rm(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 2..=2)?;
+    let mut fs = open(&opts.args[0])?;
+    let path = famfs_path(&opts.args[1])?;
+
+    let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+    log.delete_file(&path).map_err(|err| log_error(&path, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn mv(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 3..=3)?;
+    let mut fs = open(&opts.args[0])?;
+    let from = famfs_path(&opts.args[1])?;
+    let to = famfs_path(&opts.args[2])?;
+
+    let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+    log.rename(&from, &to).map_err(|err| log_error(&from, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn ln(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "s", "", 3..=3)?;
+    if !opts.has('s') {
+        return Err("only symbolic links are supported, use -s".to_string());
+    }
+    let target = Path::new(&opts.args[1]);
+    let path = famfs_path(&opts.args[2])?;
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+    let mut fs = open(&opts.args[0])?;
+    let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+    log.make_symlink(&path, target, uid, gid).map_err(|err| log_error(&path, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn readlink(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 2..=2)?;
+    let fs = open(&opts.args[0])?;
+    let path = famfs_path(&opts.args[1])?;
+
+    println!("{}", fs.read_link(&path).map_err(|err| log_error(&path, err))?.display());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn chmod(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 3..=3)?;
+    let mut fs = open(&opts.args[0])?;
+    let mode = u32::from_str_radix(&opts.args[1], 8).map_err(|_| format!("bad mode: {}", opts.args[1]))?;
+    let path = famfs_path(&opts.args[2])?;
+
+    fs.set_permissions(&path, mode).map_err(|err| log_error(&path, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn chown(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 3..=3)?;
+    let mut fs = open(&opts.args[0])?;
+    let path = famfs_path(&opts.args[2])?;
+
+    let owner = &opts.args[1];
+    let (uid, gid) = owner.split_once(':').unwrap_or((owner, ""));
+    let id = |id: &str| match id {
+        "" => Ok(None),
+        id => id.parse().map(Some).map_err(|_| format!("bad owner: {owner}")),
+    };
+    let (uid, gid) = (id(uid)?, id(gid)?);
+    if uid.is_none() && gid.is_none() {
+        return Err(format!("bad owner: {owner}"));
+    }
+
+    fs.set_owner(&path, uid, gid).map_err(|err| log_error(&path, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn truncate(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "s", 2..=2)?;
+    let mut fs = open(&opts.args[0])?;
+    let path = famfs_path(&opts.args[1])?;
+    let size = parse_size(opts.opts.get(&'s').ok_or("-s SIZE is required")?)?;
+
+    let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+    log.set_len(&path, size).map_err(|err| log_error(&path, err))?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn fsck(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 1..=1)?;
+    let device = &opts.args[0];
+    let mmaped = famfs_rs::MMAPed::open(Path::new(device)).map_err(|err| format!("{device}: {err}"))?;
+
+    let report = mmaped.fsck();
+    print!("{report}");
+
+    Ok(if report.is_clean() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn bitmap(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "", "", 1..=1)?;
+    let mut fs = open(&opts.args[0])?;
+
+    let log = fs.lock_log().map_err(|err| err.to_string())?;
+    println!("{}", log.bitmap_string());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn logplay(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "vn", "sm", 1..=1)?;
+    let mut fs = open(&opts.args[0])?;
+    let ns = fs.replay();
+
+    if opts.has('v') {
+        for (path, node) in ns.iter() {
+            let kind = match node.kind {
+                NodeKind::File => "file",
+                NodeKind::Dir => "dir ",
+                NodeKind::Symlink => "link",
+            };
+            println!("{kind} {} (seqnum {})", path.display(), node.seqnum);
+        }
+    }
+
+    let mut stats = *ns.stats();
+    if let Some(shadow) = opts.opts.get(&'s') {
+        let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+        stats = log.logplay_shadow(Path::new(shadow)).map_err(|err| format!("{shadow}: {err}"))?;
+    }
+
+    if let Some(mpt) = opts.opts.get(&'m') {
+        let mut log = fs.lock_log().map_err(|err| err.to_string())?;
+        let (played, changes) = log.logplay_mount(Path::new(mpt), opts.has('n')).map_err(|err| format!("{mpt}: {err}"))?;
+        stats = played;
+
+        let verb = if opts.has('n') { "would " } else { "" };
+        for change in changes {
+            match change {
+                MountChange::Mkdir(path) => println!("{verb}mkdir {}", path.display()),
+                MountChange::Create(path) => println!("{verb}create {}", path.display()),
+                MountChange::Symlink(path) => println!("{verb}symlink {}", path.display()),
+            }
+        }
+    }
+
+    println!("entries:     {}", stats.n_entries);
+    println!("files:       {}", stats.f_logged);
+    println!("directories: {}", stats.d_logged);
+    println!("symlinks:    {}", stats.s_logged);
+    println!("bad entries: {}", stats.bad_entries);
+
+    if opts.opts.contains_key(&'s') || opts.opts.contains_key(&'m') {
+        println!("files created:       {} ({} existed, {} errors)", stats.f_created, stats.f_existed, stats.f_errs);
+        println!("directories created: {} ({} existed, {} errors)", stats.d_created, stats.d_existed, stats.d_errs);
+    }
+    if opts.opts.contains_key(&'s') {
+        println!("yaml checked:        {} ({} bad)", stats.yaml_checked, stats.yaml_errs);
+    }
+
+    let errors = stats.bad_entries + stats.f_errs + stats.d_errs + stats.yaml_errs;
+    Ok(if errors == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn logdump(args: &[String]) -> Result<ExitCode, String> {
+    let opts = Opts::parse(args, "j", "", 1..=1)?;
+    let mut fs = open(&opts.args[0])?;
+    let dump = fs.dump_log();
+
+    if opts.has('j') {
+        println!("{}", dump.to_json());
+    } else {
+        print!("{dump}");
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -1,7 +1,10 @@
-use std::{mem::ManuallyDrop, path::Path};
+use std::{ffi::OsStr, mem::{offset_of, ManuallyDrop}, os::unix::ffi::OsStrExt, path::Path, sync::atomic::{fence, Ordering}};
 
 use uuid::Uuid;
 
+use crate::{DirtyPages, FamfsMetadataInterface};
+use crate::relpath::RelPath;
+
 pub const FAMFS_SUPER_MAGIC: u64 = 0x87b282ff; // Memory superblock magic number
 pub const FAMFS_STATFS_MAGIC_V1: u64 = 0x87b282fe; // v1 statfs magic number
 pub const FAMFS_STATFS_MAGIC: u64 = 0x87b282fd; // fuse statfs magic number
@@ -34,14 +37,50 @@ pub const FAMFS_FM_BUF_LEN: usize = 512;
 pub const FAMFS_FM_ALL_HOSTS_RO: u32 = 1 << 0;
 pub const FAMFS_FM_ALL_HOSTS_RW: u32 = 1 << 1;
 
+// Files are writable only when logged RW and not also RO
+pub fn fm_flags_writable(flags: u32) -> bool {
+    flags & FAMFS_FM_ALL_HOSTS_RW != 0 && flags & FAMFS_FM_ALL_HOSTS_RO == 0
+}
+
 pub const FAMFS_LOG_MAGIC: u64 = 0xbadcafef00d;
 
-pub(crate) const MIN_DEVSIZE: usize = 4 * 1024 * 1024 * 1024;
+pub const FAMFS_SYSTEM_UUID_PATH: &str = "/opt/famfs/system_uuid";
+
+pub const MIN_DEVSIZE: usize = 4 * 1024 * 1024 * 1024;
+
+// The on-media format is little endian, the encode/decode functions below
+// read and write it explicitly at the offsets asserted at the end of this
+// file. Structures mapped in place are only meaningful on little endian
+// hosts, other hosts have to go through encode/decode.
+
+fn put_u32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put_u64(buf: &mut [u8], offset: usize, val: u64) {
+    buf[offset..offset + 8].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put_bytes(buf: &mut [u8], offset: usize, val: &[u8]) {
+    buf[offset..offset + val.len()].copy_from_slice(val);
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(get_bytes(buf, offset))
+}
+
+fn get_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(get_bytes(buf, offset))
+}
+
+fn get_bytes<const N: usize>(buf: &[u8], offset: usize) -> [u8; N] {
+    buf[offset..offset + N].try_into().unwrap()
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct famfs_daxdev {
-    pub(crate) dd_size: usize, 
+    pub(crate) dd_size: u64, 
     pub(crate) dd_uuid: Uuid,
     pub(crate) daxdev: [u8; FAMFS_DEVNAME_LEN]
 }
@@ -71,23 +110,54 @@ impl famfs_superblock {
         famfs_system_role::FAMFS_MASTER
     }
 
-    fn regenerate_crc(&mut self) {
+    // Lays down a new superblock for a device of devsize bytes
+    pub(crate) fn format(&mut self, devsize: u64, iface: &mut dyn FamfsMetadataInterface) {
+        let sb = famfs_superblock {
+            ts_magic: FAMFS_SUPER_MAGIC,
+            ts_version: FAMFS_CURRENT_VERSION,
+            ts_log_offset: FAMFS_LOG_OFFSET,
+            ts_log_len: FAMFS_LOG_LEN,
+            ts_alloc_unit: FAMFS_ALLOC_UNIT,
+            ts_omf_ver_major: FAMFS_OMF_VER_MAJOR as u32,
+            ts_omf_ver_minor: FAMFS_OMF_VER_MINOR as u32,
+            ts_uuid: Uuid::new_v4(),
+            ts_dev_uuid: Uuid::new_v4(),
+            ts_system_uuid: Self::system_uuid(),
+            ts_crc: 0,
+            ts_pad: 0,
+            ts_sb_flags: FAMFS_PRIMARY_SB as u32,
+            ts_daxdev: famfs_daxdev {
+                dd_size: devsize,
+                dd_uuid: Uuid::nil(),
+                daxdev: [0; FAMFS_DEVNAME_LEN],
+            }
+        };
+        unsafe { std::ptr::copy_nonoverlapping(sb.encode().as_ptr(), (self as *mut Self).cast::<u8>(), size_of::<Self>()) };
+
+        iface.mark_dirty(DirtyPages::superblock(0, size_of::<famfs_superblock>()));
+        self.regenerate_crc(iface);
+    }
+
+    // Like the C tools, the system uuid is shared by everything on the
+    // host through a file, hosts without one get the nil uuid
+    fn system_uuid() -> Uuid {
+        std::fs::read_to_string(FAMFS_SYSTEM_UUID_PATH)
+            .ok()
+            .and_then(|uuid| Uuid::parse_str(uuid.trim()).ok())
+            .unwrap_or(Uuid::nil())
+    }
+
+    fn regenerate_crc(&mut self, iface: &mut dyn FamfsMetadataInterface) {
         self.ts_crc = self.generate_crc();
+        iface.mark_dirty(DirtyPages::superblock(std::mem::offset_of!(famfs_superblock, ts_crc), size_of::<u32>()));
     }
 
+    // Covers every field before ts_crc in their little endian encoding,
+    // the same bytes the C implementation hashes field by field
     fn generate_crc(&self) -> u32 {
         let mut crc = crc32fast::Hasher::new();
 
-        crc.update(&self.ts_magic.to_ne_bytes()); // is it native endian?
-        crc.update(&self.ts_version.to_ne_bytes());
-        crc.update(&self.ts_log_offset.to_ne_bytes());
-        crc.update(&self.ts_log_len.to_ne_bytes());
-        crc.update(&self.ts_alloc_unit.to_ne_bytes());
-        crc.update(&self.ts_omf_ver_major.to_ne_bytes());
-        crc.update(&self.ts_omf_ver_minor.to_ne_bytes());
-        crc.update(self.ts_uuid.as_bytes());
-        crc.update(self.ts_dev_uuid.as_bytes());
-        crc.update(self.ts_system_uuid.as_bytes());
+        crc.update(&self.encode()[..offset_of!(famfs_superblock, ts_crc)]);
 
         crc.finalize()
     }
@@ -116,7 +186,78 @@ impl famfs_superblock {
     fn daxdev_uuid() {}
 
     pub fn daxdev_size(&self) -> usize {
-        self.ts_daxdev.dd_size
+        self.ts_daxdev.dd_size as usize
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.ts_uuid
+    }
+
+    pub fn log_offset(&self) -> u64 {
+        self.ts_log_offset
+    }
+
+    pub fn log_len(&self) -> u64 {
+        self.ts_log_len
+    }
+
+    pub fn alloc_unit(&self) -> u64 {
+        self.ts_alloc_unit
+    }
+
+    pub fn encode(&self) -> [u8; size_of::<famfs_superblock>()] {
+        let mut buf = [0; size_of::<famfs_superblock>()];
+
+        put_u64(&mut buf, offset_of!(famfs_superblock, ts_magic), self.ts_magic);
+        put_u64(&mut buf, offset_of!(famfs_superblock, ts_version), self.ts_version);
+        put_u64(&mut buf, offset_of!(famfs_superblock, ts_log_offset), self.ts_log_offset);
+        put_u64(&mut buf, offset_of!(famfs_superblock, ts_log_len), self.ts_log_len);
+        put_u64(&mut buf, offset_of!(famfs_superblock, ts_alloc_unit), self.ts_alloc_unit);
+        put_u32(&mut buf, offset_of!(famfs_superblock, ts_omf_ver_major), self.ts_omf_ver_major);
+        put_u32(&mut buf, offset_of!(famfs_superblock, ts_omf_ver_minor), self.ts_omf_ver_minor);
+        put_bytes(&mut buf, offset_of!(famfs_superblock, ts_uuid), self.ts_uuid.as_bytes());
+        put_bytes(&mut buf, offset_of!(famfs_superblock, ts_dev_uuid), self.ts_dev_uuid.as_bytes());
+        put_bytes(&mut buf, offset_of!(famfs_superblock, ts_system_uuid), self.ts_system_uuid.as_bytes());
+        put_u32(&mut buf, offset_of!(famfs_superblock, ts_crc), self.ts_crc);
+        put_u32(&mut buf, offset_of!(famfs_superblock, ts_pad), self.ts_pad);
+        put_u32(&mut buf, offset_of!(famfs_superblock, ts_sb_flags), self.ts_sb_flags);
+
+        let dd = offset_of!(famfs_superblock, ts_daxdev);
+        put_u64(&mut buf, dd + offset_of!(famfs_daxdev, dd_size), self.ts_daxdev.dd_size);
+        put_bytes(&mut buf, dd + offset_of!(famfs_daxdev, dd_uuid), self.ts_daxdev.dd_uuid.as_bytes());
+        put_bytes(&mut buf, dd + offset_of!(famfs_daxdev, daxdev), &self.ts_daxdev.daxdev);
+
+        buf
+    }
+
+    // Returns None if buf is too short to hold a superblock
+    pub fn decode(buf: &[u8]) -> Option<famfs_superblock> {
+        if buf.len() < size_of::<famfs_superblock>() {
+            return None;
+        }
+
+        let dd = offset_of!(famfs_superblock, ts_daxdev);
+
+        Some(famfs_superblock {
+            ts_magic: get_u64(buf, offset_of!(famfs_superblock, ts_magic)),
+            ts_version: get_u64(buf, offset_of!(famfs_superblock, ts_version)),
+            ts_log_offset: get_u64(buf, offset_of!(famfs_superblock, ts_log_offset)),
+            ts_log_len: get_u64(buf, offset_of!(famfs_superblock, ts_log_len)),
+            ts_alloc_unit: get_u64(buf, offset_of!(famfs_superblock, ts_alloc_unit)),
+            ts_omf_ver_major: get_u32(buf, offset_of!(famfs_superblock, ts_omf_ver_major)),
+            ts_omf_ver_minor: get_u32(buf, offset_of!(famfs_superblock, ts_omf_ver_minor)),
+            ts_uuid: Uuid::from_bytes(get_bytes(buf, offset_of!(famfs_superblock, ts_uuid))),
+            ts_dev_uuid: Uuid::from_bytes(get_bytes(buf, offset_of!(famfs_superblock, ts_dev_uuid))),
+            ts_system_uuid: Uuid::from_bytes(get_bytes(buf, offset_of!(famfs_superblock, ts_system_uuid))),
+            ts_crc: get_u32(buf, offset_of!(famfs_superblock, ts_crc)),
+            ts_pad: get_u32(buf, offset_of!(famfs_superblock, ts_pad)),
+            ts_sb_flags: get_u32(buf, offset_of!(famfs_superblock, ts_sb_flags)),
+            ts_daxdev: famfs_daxdev {
+                dd_size: get_u64(buf, dd + offset_of!(famfs_daxdev, dd_size)),
+                dd_uuid: Uuid::from_bytes(get_bytes(buf, dd + offset_of!(famfs_daxdev, dd_uuid))),
+                daxdev: get_bytes(buf, dd + offset_of!(famfs_daxdev, daxdev)),
+            }
+        })
     }
 }
 
@@ -134,33 +275,83 @@ enum famfs_log_ext_type {
     FAMFS_EXT_INTERLEAVE = 1
 }
 
+impl famfs_log_ext_type {
+    // the media may hold any value, only 1 selects the interleaved map
+    fn from_raw(raw: u32) -> famfs_log_ext_type {
+        match raw {
+            1 => famfs_log_ext_type::FAMFS_EXT_INTERLEAVE,
+            _ => famfs_log_ext_type::FAMFS_EXT_SIMPLE,
+        }
+    }
+}
+
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Default)]
-pub(crate) struct famfs_simple_extent {
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct famfs_simple_extent {
     pub se_devindex:    u64,
     pub se_offset:      u64, 
     pub se_len:         u64
 }
 
+impl famfs_simple_extent {
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u64(buf, offset_of!(famfs_simple_extent, se_devindex), self.se_devindex);
+        put_u64(buf, offset_of!(famfs_simple_extent, se_offset), self.se_offset);
+        put_u64(buf, offset_of!(famfs_simple_extent, se_len), self.se_len);
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_simple_extent {
+        famfs_simple_extent {
+            se_devindex: get_u64(buf, offset_of!(famfs_simple_extent, se_devindex)),
+            se_offset: get_u64(buf, offset_of!(famfs_simple_extent, se_offset)),
+            se_len: get_u64(buf, offset_of!(famfs_simple_extent, se_len)),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct famfs_interleaved_ext {
+pub struct famfs_interleaved_ext {
     pub ie_nstrips:     u64,
     pub ie_chunk_size:  u64,
     pub ie_strips: [famfs_simple_extent; FAMFS_MAX_SIMPLE_EXTENTS]
 }
 
+impl famfs_interleaved_ext {
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u64(buf, offset_of!(famfs_interleaved_ext, ie_nstrips), self.ie_nstrips);
+        put_u64(buf, offset_of!(famfs_interleaved_ext, ie_chunk_size), self.ie_chunk_size);
+
+        let strips = offset_of!(famfs_interleaved_ext, ie_strips);
+        for (i, strip) in self.ie_strips.iter().enumerate() {
+            strip.encode_into(&mut buf[strips + i * size_of::<famfs_simple_extent>()..]);
+        }
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_interleaved_ext {
+        let strips = offset_of!(famfs_interleaved_ext, ie_strips);
+
+        famfs_interleaved_ext {
+            ie_nstrips: get_u64(buf, offset_of!(famfs_interleaved_ext, ie_nstrips)),
+            ie_chunk_size: get_u64(buf, offset_of!(famfs_interleaved_ext, ie_chunk_size)),
+            ie_strips: std::array::from_fn(|i| {
+                famfs_simple_extent::decode_from(&buf[strips + i * size_of::<famfs_simple_extent>()..])
+            }),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct famfs_log_fmap_union_simple_extent {
+pub struct famfs_log_fmap_union_simple_extent {
     pub fmap_nextents: u32,
     pub se: [famfs_simple_extent; FAMFS_MAX_SIMPLE_EXTENTS]
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
-pub(crate) struct famfs_log_fmap_union_interleaved_extent {
+pub struct famfs_log_fmap_union_interleaved_extent {
     pub fmap_niext: u32,
     pub se: [famfs_interleaved_ext; FAMFS_MAX_INTERLEAVED_EXTENTS]
 }
@@ -175,11 +366,110 @@ union famfs_log_fmap_union {
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct famfs_log_fmap {
-    fmap_ext_type: famfs_log_ext_type,
+    fmap_ext_type: u32, // famfs_log_ext_type
     inner: famfs_log_fmap_union
 }
 
 impl famfs_log_fmap {
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u32(buf, offset_of!(famfs_log_fmap, fmap_ext_type), self.fmap_ext_type);
+
+        let inner = &mut buf[offset_of!(famfs_log_fmap, inner)..];
+        match famfs_log_ext_type::from_raw(self.fmap_ext_type) {
+            famfs_log_ext_type::FAMFS_EXT_SIMPLE => {
+                let simple = unsafe { &self.inner.simple };
+                let se = offset_of!(famfs_log_fmap_union_simple_extent, se);
+
+                put_u32(inner, offset_of!(famfs_log_fmap_union_simple_extent, fmap_nextents), simple.fmap_nextents);
+                for (i, extent) in simple.se.iter().enumerate() {
+                    extent.encode_into(&mut inner[se + i * size_of::<famfs_simple_extent>()..]);
+                }
+            },
+            famfs_log_ext_type::FAMFS_EXT_INTERLEAVE => {
+                let interleaved = unsafe { &self.inner.interleaved };
+                let se = offset_of!(famfs_log_fmap_union_interleaved_extent, se);
+
+                put_u32(inner, offset_of!(famfs_log_fmap_union_interleaved_extent, fmap_niext), interleaved.fmap_niext);
+                for (i, extent) in interleaved.se.iter().enumerate() {
+                    extent.encode_into(&mut inner[se + i * size_of::<famfs_interleaved_ext>()..]);
+                }
+            },
+        }
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_log_fmap {
+        let fmap_ext_type = get_u32(buf, offset_of!(famfs_log_fmap, fmap_ext_type));
+        let inner = &buf[offset_of!(famfs_log_fmap, inner)..];
+
+        let inner = match famfs_log_ext_type::from_raw(fmap_ext_type) {
+            famfs_log_ext_type::FAMFS_EXT_SIMPLE => {
+                let se = offset_of!(famfs_log_fmap_union_simple_extent, se);
+                famfs_log_fmap_union {
+                    simple: ManuallyDrop::new(famfs_log_fmap_union_simple_extent {
+                        fmap_nextents: get_u32(inner, offset_of!(famfs_log_fmap_union_simple_extent, fmap_nextents)),
+                        se: std::array::from_fn(|i| {
+                            famfs_simple_extent::decode_from(&inner[se + i * size_of::<famfs_simple_extent>()..])
+                        }),
+                    })
+                }
+            },
+            famfs_log_ext_type::FAMFS_EXT_INTERLEAVE => {
+                let se = offset_of!(famfs_log_fmap_union_interleaved_extent, se);
+                famfs_log_fmap_union {
+                    interleaved: ManuallyDrop::new(famfs_log_fmap_union_interleaved_extent {
+                        fmap_niext: get_u32(inner, offset_of!(famfs_log_fmap_union_interleaved_extent, fmap_niext)),
+                        se: std::array::from_fn(|i| {
+                            famfs_interleaved_ext::decode_from(&inner[se + i * size_of::<famfs_interleaved_ext>()..])
+                        }),
+                    })
+                }
+            },
+        };
+
+        famfs_log_fmap { fmap_ext_type, inner }
+    }
+
+    // None if there are more extents than an fmap holds
+    pub fn simple(extents: &[famfs_simple_extent]) -> Option<famfs_log_fmap> {
+        if extents.len() > FAMFS_MAX_SIMPLE_EXTENTS {
+            return None;
+        }
+
+        let mut simple = famfs_log_fmap_union_simple_extent {
+            fmap_nextents: extents.len() as u32,
+            se: [famfs_simple_extent::default(); FAMFS_MAX_SIMPLE_EXTENTS],
+        };
+        simple.se[..extents.len()].copy_from_slice(extents);
+
+        Some(famfs_log_fmap {
+            fmap_ext_type: famfs_log_ext_type::FAMFS_EXT_SIMPLE as u32,
+            inner: famfs_log_fmap_union { simple: ManuallyDrop::new(simple) },
+        })
+    }
+
+    // None if there are more interleaved extents than an fmap holds
+    pub fn interleaved(extents: &[famfs_interleaved_ext]) -> Option<famfs_log_fmap> {
+        if extents.len() > FAMFS_MAX_INTERLEAVED_EXTENTS {
+            return None;
+        }
+
+        let empty = famfs_interleaved_ext {
+            ie_nstrips: 0,
+            ie_chunk_size: 0,
+            ie_strips: [famfs_simple_extent::default(); FAMFS_MAX_SIMPLE_EXTENTS],
+        };
+        let mut interleaved = famfs_log_fmap_union_interleaved_extent {
+            fmap_niext: extents.len() as u32,
+            se: [empty; FAMFS_MAX_INTERLEAVED_EXTENTS],
+        };
+        interleaved.se[..extents.len()].copy_from_slice(extents);
+
+        Some(famfs_log_fmap {
+            fmap_ext_type: famfs_log_ext_type::FAMFS_EXT_INTERLEAVE as u32,
+            inner: famfs_log_fmap_union { interleaved: ManuallyDrop::new(interleaved) },
+        })
+    }
+
     pub fn generate_simple_fmap(size: u64, offset: u64) -> famfs_log_fmap {
         let mut simple_extent = famfs_log_fmap_union_simple_extent {
             fmap_nextents: 1,
@@ -193,7 +483,7 @@ impl famfs_log_fmap {
         };
 
         famfs_log_fmap {
-            fmap_ext_type: famfs_log_ext_type::FAMFS_EXT_SIMPLE,
+            fmap_ext_type: famfs_log_ext_type::FAMFS_EXT_SIMPLE as u32,
             inner: famfs_log_fmap_union {
                 simple: {
                     ManuallyDrop::new(simple_extent)
@@ -204,12 +494,37 @@ impl famfs_log_fmap {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum famfs_log_entry_type {
-    FAMFS_LOG_FILE,
-    FAMFS_LOG_MKDIR,
-    FAMFS_LOG_DELETE,
-    FAMFS_LOG_INVALID
+    FAMFS_LOG_FILE = 0,
+    FAMFS_LOG_MKDIR = 1,
+    FAMFS_LOG_DELETE = 2,
+    FAMFS_LOG_INVALID = 3, // the C tools' value, the types below come after it
+    FAMFS_LOG_RENAME = 4, // not known to the C tools, which read it as invalid
+    FAMFS_LOG_SETATTR = 5, // nor is this one
+    FAMFS_LOG_SYMLINK = 6, // or this
+    FAMFS_LOG_RESIZE = 7, // or this, older readers skip all four
+}
+
+impl famfs_log_entry_type {
+    // types this implementation doesn't know about read as invalid
+    fn from_raw(raw: u32) -> famfs_log_entry_type {
+        match raw {
+            0 => famfs_log_entry_type::FAMFS_LOG_FILE,
+            1 => famfs_log_entry_type::FAMFS_LOG_MKDIR,
+            2 => famfs_log_entry_type::FAMFS_LOG_DELETE,
+            4 => famfs_log_entry_type::FAMFS_LOG_RENAME,
+            5 => famfs_log_entry_type::FAMFS_LOG_SETATTR,
+            6 => famfs_log_entry_type::FAMFS_LOG_SYMLINK,
+            7 => famfs_log_entry_type::FAMFS_LOG_RESIZE,
+            _ => famfs_log_entry_type::FAMFS_LOG_INVALID,
+        }
+    }
+}
+
+fn relpath_bytes(relpath: &[u8; FAMFS_MAX_PATHLEN]) -> &Path {
+    let len = relpath.iter().position(|b| *b == 0).unwrap_or(relpath.len());
+    Path::new(OsStr::from_bytes(&relpath[..len]))
 }
 
 #[repr(C)]
@@ -218,12 +533,163 @@ pub struct famfs_log_mkdir {
     md_uid: u32, 
     md_gid: u32, 
     md_mode: u32, 
-    md_relpath: [u8; FAMFS_MAX_PATHLEN]
+    pub(crate) md_relpath: [u8; FAMFS_MAX_PATHLEN]
 }
 
 impl famfs_log_mkdir {
-    pub fn relpath(&self) -> &str {
-        std::str::from_utf8(&self.md_relpath).unwrap()
+    // The path as logged, up to its NUL terminator
+    pub fn relpath(&self) -> &Path {
+        relpath_bytes(&self.md_relpath)
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.md_uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.md_gid
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.md_mode
+    }
+
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u32(buf, offset_of!(famfs_log_mkdir, md_uid), self.md_uid);
+        put_u32(buf, offset_of!(famfs_log_mkdir, md_gid), self.md_gid);
+        put_u32(buf, offset_of!(famfs_log_mkdir, md_mode), self.md_mode);
+        put_bytes(buf, offset_of!(famfs_log_mkdir, md_relpath), &self.md_relpath);
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_log_mkdir {
+        famfs_log_mkdir {
+            md_uid: get_u32(buf, offset_of!(famfs_log_mkdir, md_uid)),
+            md_gid: get_u32(buf, offset_of!(famfs_log_mkdir, md_gid)),
+            md_mode: get_u32(buf, offset_of!(famfs_log_mkdir, md_mode)),
+            md_relpath: get_bytes(buf, offset_of!(famfs_log_mkdir, md_relpath)),
+        }
+    }
+}
+
+// New mode and owner for a file or directory, replacing whatever its
+// creating entry (or an earlier setattr) said. All three are always set.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct famfs_log_setattr {
+    sa_uid: u32,
+    sa_gid: u32,
+    sa_mode: u32,
+    pub(crate) sa_relpath: [u8; FAMFS_MAX_PATHLEN]
+}
+
+impl famfs_log_setattr {
+    pub fn relpath(&self) -> &Path {
+        relpath_bytes(&self.sa_relpath)
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.sa_uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.sa_gid
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.sa_mode
+    }
+
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u32(buf, offset_of!(famfs_log_setattr, sa_uid), self.sa_uid);
+        put_u32(buf, offset_of!(famfs_log_setattr, sa_gid), self.sa_gid);
+        put_u32(buf, offset_of!(famfs_log_setattr, sa_mode), self.sa_mode);
+        put_bytes(buf, offset_of!(famfs_log_setattr, sa_relpath), &self.sa_relpath);
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_log_setattr {
+        famfs_log_setattr {
+            sa_uid: get_u32(buf, offset_of!(famfs_log_setattr, sa_uid)),
+            sa_gid: get_u32(buf, offset_of!(famfs_log_setattr, sa_gid)),
+            sa_mode: get_u32(buf, offset_of!(famfs_log_setattr, sa_mode)),
+            sa_relpath: get_bytes(buf, offset_of!(famfs_log_setattr, sa_relpath)),
+        }
+    }
+}
+
+// A symbolic link at relpath. The target is stored as given, relative
+// to the link's directory or, with a leading /, to the famfs root.
+// Links have no mode of their own.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct famfs_log_symlink {
+    sl_uid: u32,
+    sl_gid: u32,
+    pub(crate) sl_relpath: [u8; FAMFS_MAX_PATHLEN],
+    sl_target: [u8; FAMFS_MAX_PATHLEN]
+}
+
+impl famfs_log_symlink {
+    pub fn relpath(&self) -> &Path {
+        relpath_bytes(&self.sl_relpath)
+    }
+
+    pub fn target(&self) -> &Path {
+        relpath_bytes(&self.sl_target)
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.sl_uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.sl_gid
+    }
+
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u32(buf, offset_of!(famfs_log_symlink, sl_uid), self.sl_uid);
+        put_u32(buf, offset_of!(famfs_log_symlink, sl_gid), self.sl_gid);
+        put_bytes(buf, offset_of!(famfs_log_symlink, sl_relpath), &self.sl_relpath);
+        put_bytes(buf, offset_of!(famfs_log_symlink, sl_target), &self.sl_target);
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_log_symlink {
+        famfs_log_symlink {
+            sl_uid: get_u32(buf, offset_of!(famfs_log_symlink, sl_uid)),
+            sl_gid: get_u32(buf, offset_of!(famfs_log_symlink, sl_gid)),
+            sl_relpath: get_bytes(buf, offset_of!(famfs_log_symlink, sl_relpath)),
+            sl_target: get_bytes(buf, offset_of!(famfs_log_symlink, sl_target)),
+        }
+    }
+}
+
+// Moves a file or directory, and everything under a directory, to a new
+// path. The entries that created them keep their old paths.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct famfs_log_rename {
+    pub(crate) rn_relpath: [u8; FAMFS_MAX_PATHLEN],
+    pub(crate) rn_new_relpath: [u8; FAMFS_MAX_PATHLEN]
+}
+
+impl famfs_log_rename {
+    pub fn relpath(&self) -> &Path {
+        relpath_bytes(&self.rn_relpath)
+    }
+
+    pub fn new_relpath(&self) -> &Path {
+        relpath_bytes(&self.rn_new_relpath)
+    }
+
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_bytes(buf, offset_of!(famfs_log_rename, rn_relpath), &self.rn_relpath);
+        put_bytes(buf, offset_of!(famfs_log_rename, rn_new_relpath), &self.rn_new_relpath);
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_log_rename {
+        famfs_log_rename {
+            rn_relpath: get_bytes(buf, offset_of!(famfs_log_rename, rn_relpath)),
+            rn_new_relpath: get_bytes(buf, offset_of!(famfs_log_rename, rn_new_relpath)),
+        }
     }
 }
 
@@ -242,30 +708,175 @@ pub struct famfs_log_file_meta {
 }
 
 impl famfs_log_file_meta {
-    pub fn relpath(&self) -> &str {
-        std::str::from_utf8(&self.fm_relpath).unwrap()
+    pub fn new(
+        relpath: [u8; FAMFS_MAX_PATHLEN],
+        size: u64,
+        flags: u32,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+        fmap: famfs_log_fmap
+    ) -> famfs_log_file_meta {
+        famfs_log_file_meta {
+            fm_size: size,
+            fm_flags: flags,
+            fm_uid: uid,
+            fm_gid: gid,
+            fm_mode: mode,
+            fm_relpath: relpath,
+            fm_fmap: fmap
+        }
+    }
+
+    // The path as logged, up to its NUL terminator
+    pub fn relpath(&self) -> &Path {
+        relpath_bytes(&self.fm_relpath)
+    }
+
+    // The same file at another path
+    pub fn with_relpath(&self, path: &RelPath) -> famfs_log_file_meta {
+        famfs_log_file_meta { fm_relpath: path.to_relpath(), ..*self }
+    }
+
+    // The same file with another mode and owner
+    pub fn with_attrs(&self, mode: u32, uid: u32, gid: u32) -> famfs_log_file_meta {
+        famfs_log_file_meta { fm_mode: mode, fm_uid: uid, fm_gid: gid, ..*self }
+    }
+
+    fn encode_into(&self, buf: &mut [u8]) {
+        put_u64(buf, offset_of!(famfs_log_file_meta, fm_size), self.fm_size);
+        put_u32(buf, offset_of!(famfs_log_file_meta, fm_flags), self.fm_flags);
+        put_u32(buf, offset_of!(famfs_log_file_meta, fm_uid), self.fm_uid);
+        put_u32(buf, offset_of!(famfs_log_file_meta, fm_gid), self.fm_gid);
+        put_u32(buf, offset_of!(famfs_log_file_meta, fm_mode), self.fm_mode);
+        put_bytes(buf, offset_of!(famfs_log_file_meta, fm_relpath), &self.fm_relpath);
+        self.fm_fmap.encode_into(&mut buf[offset_of!(famfs_log_file_meta, fm_fmap)..]);
+    }
+
+    fn decode_from(buf: &[u8]) -> famfs_log_file_meta {
+        famfs_log_file_meta {
+            fm_size: get_u64(buf, offset_of!(famfs_log_file_meta, fm_size)),
+            fm_flags: get_u32(buf, offset_of!(famfs_log_file_meta, fm_flags)),
+            fm_uid: get_u32(buf, offset_of!(famfs_log_file_meta, fm_uid)),
+            fm_gid: get_u32(buf, offset_of!(famfs_log_file_meta, fm_gid)),
+            fm_mode: get_u32(buf, offset_of!(famfs_log_file_meta, fm_mode)),
+            fm_relpath: get_bytes(buf, offset_of!(famfs_log_file_meta, fm_relpath)),
+            fm_fmap: famfs_log_fmap::decode_from(&buf[offset_of!(famfs_log_file_meta, fm_fmap)..]),
+        }
+    }
+
+    // Every extent backing the file, the strips of an interleaved map are
+    // listed in order. Counts past the on-media maximum are clamped.
+    pub fn extent_list(&self) -> Vec<famfs_simple_extent> {
+        match self.get_extent() {
+            Extent::Simple { extent } => {
+                let nextents = std::cmp::min(extent.fmap_nextents as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+                extent.se[..nextents].to_vec()
+            },
+            Extent::Interleaved { extent } => {
+                let niext = std::cmp::min(extent.fmap_niext as usize, FAMFS_MAX_INTERLEAVED_EXTENTS);
+                extent.se[..niext]
+                    .iter()
+                    .flat_map(|ie| {
+                        let nstrips = std::cmp::min(ie.ie_nstrips as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+                        ie.ie_strips[..nstrips].to_vec()
+                    })
+                    .collect()
+            },
+        }
+    }
+
+    /// The (devindex, offset, len) ranges holding the file's bytes in file
+    /// order, ending at fm_size. Interleaved extents are split into their
+    /// chunks, which rotate across the strips: chunk k lives in strip
+    /// k % nstrips at (k / nstrips) * chunk_size.
+    pub fn segments(&self) -> Vec<famfs_simple_extent> {
+        let mut segments = Vec::new();
+        let mut left = self.fm_size;
+
+        let mut push = |devindex: u64, offset: u64, len: u64| {
+            let len = std::cmp::min(len, left);
+            if len > 0 {
+                segments.push(famfs_simple_extent { se_devindex: devindex, se_offset: offset, se_len: len });
+                left -= len;
+            }
+            left > 0
+        };
+
+        match self.get_extent() {
+            Extent::Simple { extent } => {
+                let nextents = std::cmp::min(extent.fmap_nextents as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+                for se in &extent.se[..nextents] {
+                    if !push(se.se_devindex, se.se_offset, se.se_len) {
+                        break;
+                    }
+                }
+            },
+            Extent::Interleaved { extent } => {
+                let niext = std::cmp::min(extent.fmap_niext as usize, FAMFS_MAX_INTERLEAVED_EXTENTS);
+                'ext: for ie in &extent.se[..niext] {
+                    let nstrips = std::cmp::min(ie.ie_nstrips as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+                    if nstrips == 0 || ie.ie_chunk_size == 0 {
+                        continue;
+                    }
+
+                    // stops at the first chunk whose strip is used up
+                    for chunk in 0.. {
+                        let strip = &ie.ie_strips[chunk % nstrips];
+                        let offset = (chunk / nstrips) as u64 * ie.ie_chunk_size;
+                        if offset >= strip.se_len {
+                            break;
+                        }
+
+                        let len = std::cmp::min(ie.ie_chunk_size, strip.se_len - offset);
+                        if !push(strip.se_devindex, strip.se_offset + offset, len) {
+                            break 'ext;
+                        }
+                    }
+                }
+            },
+        }
+
+        segments
     }
 
     pub fn get_extent(&self) -> Extent {
-        match self.fm_fmap.fmap_ext_type {
+        match famfs_log_ext_type::from_raw(self.fm_fmap.fmap_ext_type) {
             famfs_log_ext_type::FAMFS_EXT_SIMPLE => Extent::Simple { extent: unsafe { *self.fm_fmap.inner.simple } },
             famfs_log_ext_type::FAMFS_EXT_INTERLEAVE =>  Extent::Interleaved { extent: unsafe { *self.fm_fmap.inner.interleaved } },
         }
     }
 }
 
+// Two file metas are the same if they go to the media as the same bytes
+impl PartialEq for famfs_log_file_meta {
+    fn eq(&self, other: &famfs_log_file_meta) -> bool {
+        let mut this = [0; size_of::<famfs_log_file_meta>()];
+        let mut that = [0; size_of::<famfs_log_file_meta>()];
+        self.encode_into(&mut this);
+        other.encode_into(&mut that);
+
+        this == that
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 union famfs_log_entry_union {
     famfs_fm: std::mem::ManuallyDrop<famfs_log_file_meta>,
-    famfs_md: std::mem::ManuallyDrop<famfs_log_mkdir>
+    famfs_md: std::mem::ManuallyDrop<famfs_log_mkdir>,
+    famfs_rn: std::mem::ManuallyDrop<famfs_log_rename>,
+    famfs_sa: std::mem::ManuallyDrop<famfs_log_setattr>,
+    famfs_sl: std::mem::ManuallyDrop<famfs_log_symlink>,
+    // entry types we can't interpret are carried as opaque bytes
+    famfs_raw: [u8; size_of::<famfs_log_file_meta>()]
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct famfs_log_entry {
     famfs_log_entry_seqnum: u64, 
-    famfs_log_entry_type: famfs_log_entry_type, 
+    famfs_log_entry_type: u32, // famfs_log_entry_type
     famfs_log_entry_log: famfs_log_entry_union,
     famfs_log_entry_crc: u32,
     famfs_pad:           u32 // AHHH
@@ -280,7 +891,15 @@ pub enum Extent {
 pub enum LogEntry<'a> {
     File {file_meta: &'a famfs_log_file_meta},
     MakeDir {dir_meta: &'a famfs_log_mkdir},
-    Delete,
+    // A delete carries the metadata of the file it removes, so replay can
+    // release its extents without looking the file up
+    Delete {file_meta: &'a famfs_log_file_meta},
+    Rename {rename: &'a famfs_log_rename},
+    SetAttr {attr: &'a famfs_log_setattr},
+    Symlink {symlink: &'a famfs_log_symlink},
+    // A file's new size and map, replay puts it in place of the entry the
+    // file came from
+    Resize {file_meta: &'a famfs_log_file_meta},
     Invalid
 }
 
@@ -290,18 +909,24 @@ impl famfs_log_entry {
     }
 
     pub fn entry_type(&self) -> famfs_log_entry_type {
+        famfs_log_entry_type::from_raw(self.famfs_log_entry_type)
+    }
+
+    // The type as stored, entry_type maps unknown values to invalid
+    pub fn raw_type(&self) -> u32 {
         self.famfs_log_entry_type
     }
 
+    pub fn crc(&self) -> u32 {
+        self.famfs_log_entry_crc
+    }
+
+    // Covers everything before the crc in its little endian encoding, with
+    // padding and the unused part of the union as zeroes
     pub fn generate_crc(&self) -> u32 {
         let mut crc32 = crc32fast::Hasher::new();
 
-        let ptr = (self as *const Self).cast::<u8>();
-        let crc_bytes = size_of::<Self>() - size_of::<u64>();
-
-        let raw_buf = unsafe { std::slice::from_raw_parts(ptr, crc_bytes) };
-
-        crc32.update(raw_buf);
+        crc32.update(&self.encode()[..offset_of!(famfs_log_entry, famfs_log_entry_crc)]);
 
         crc32.finalize()
     }
@@ -314,15 +939,105 @@ impl famfs_log_entry {
         self.famfs_log_entry_crc == self.generate_crc()
     }
 
-    pub fn get_entry_type(&self) -> LogEntry {
-        match self.famfs_log_entry_type {
+    pub fn encode(&self) -> [u8; size_of::<famfs_log_entry>()] {
+        let mut buf = [0; size_of::<famfs_log_entry>()];
+
+        put_u64(&mut buf, offset_of!(famfs_log_entry, famfs_log_entry_seqnum), self.famfs_log_entry_seqnum);
+        put_u32(&mut buf, offset_of!(famfs_log_entry, famfs_log_entry_type), self.famfs_log_entry_type);
+
+        let log = &mut buf[offset_of!(famfs_log_entry, famfs_log_entry_log)..];
+        match self.get_entry_type() {
+            LogEntry::File { file_meta } | LogEntry::Delete { file_meta } | LogEntry::Resize { file_meta } => {
+                file_meta.encode_into(log)
+            },
+            LogEntry::MakeDir { dir_meta } => dir_meta.encode_into(log),
+            LogEntry::Rename { rename } => rename.encode_into(log),
+            LogEntry::SetAttr { attr } => attr.encode_into(log),
+            LogEntry::Symlink { symlink } => symlink.encode_into(log),
+            LogEntry::Invalid => {
+                put_bytes(log, 0, unsafe { &self.famfs_log_entry_log.famfs_raw })
+            },
+        }
+
+        put_u32(&mut buf, offset_of!(famfs_log_entry, famfs_log_entry_crc), self.famfs_log_entry_crc);
+        put_u32(&mut buf, offset_of!(famfs_log_entry, famfs_pad), self.famfs_pad);
+
+        buf
+    }
+
+    // Returns None if buf is too short to hold an entry
+    pub fn decode(buf: &[u8]) -> Option<famfs_log_entry> {
+        if buf.len() < size_of::<famfs_log_entry>() {
+            return None;
+        }
+
+        let entry_type = get_u32(buf, offset_of!(famfs_log_entry, famfs_log_entry_type));
+        let log = &buf[offset_of!(famfs_log_entry, famfs_log_entry_log)..];
+
+        let famfs_log_entry_log = match famfs_log_entry_type::from_raw(entry_type) {
+            famfs_log_entry_type::FAMFS_LOG_FILE
+            | famfs_log_entry_type::FAMFS_LOG_DELETE
+            | famfs_log_entry_type::FAMFS_LOG_RESIZE => famfs_log_entry_union {
+                famfs_fm: ManuallyDrop::new(famfs_log_file_meta::decode_from(log))
+            },
+            famfs_log_entry_type::FAMFS_LOG_MKDIR => {
+                // start from zeroes so the rest of the union is defined
+                let mut union = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+                union.famfs_md = ManuallyDrop::new(famfs_log_mkdir::decode_from(log));
+                union
+            },
+            famfs_log_entry_type::FAMFS_LOG_RENAME => {
+                let mut union = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+                union.famfs_rn = ManuallyDrop::new(famfs_log_rename::decode_from(log));
+                union
+            },
+            famfs_log_entry_type::FAMFS_LOG_SETATTR => {
+                let mut union = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+                union.famfs_sa = ManuallyDrop::new(famfs_log_setattr::decode_from(log));
+                union
+            },
+            famfs_log_entry_type::FAMFS_LOG_SYMLINK => {
+                let mut union = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+                union.famfs_sl = ManuallyDrop::new(famfs_log_symlink::decode_from(log));
+                union
+            },
+            famfs_log_entry_type::FAMFS_LOG_INVALID => famfs_log_entry_union {
+                famfs_raw: get_bytes(log, 0)
+            },
+        };
+
+        Some(famfs_log_entry {
+            famfs_log_entry_seqnum: get_u64(buf, offset_of!(famfs_log_entry, famfs_log_entry_seqnum)),
+            famfs_log_entry_type: entry_type,
+            famfs_log_entry_log,
+            famfs_log_entry_crc: get_u32(buf, offset_of!(famfs_log_entry, famfs_log_entry_crc)),
+            famfs_pad: get_u32(buf, offset_of!(famfs_log_entry, famfs_pad)),
+        })
+    }
+
+    pub fn get_entry_type(&self) -> LogEntry<'_> {
+        match self.entry_type() {
             famfs_log_entry_type::FAMFS_LOG_FILE => {
                 LogEntry::File { file_meta: unsafe { &self.famfs_log_entry_log.famfs_fm } }
             },
             famfs_log_entry_type::FAMFS_LOG_MKDIR => LogEntry::MakeDir { 
                 dir_meta: unsafe { &self.famfs_log_entry_log.famfs_md } 
             },
-            famfs_log_entry_type::FAMFS_LOG_DELETE => LogEntry::Delete,
+            famfs_log_entry_type::FAMFS_LOG_DELETE => LogEntry::Delete {
+                file_meta: unsafe { &self.famfs_log_entry_log.famfs_fm }
+            },
+            famfs_log_entry_type::FAMFS_LOG_RENAME => LogEntry::Rename {
+                rename: unsafe { &self.famfs_log_entry_log.famfs_rn }
+            },
+            famfs_log_entry_type::FAMFS_LOG_SETATTR => LogEntry::SetAttr {
+                attr: unsafe { &self.famfs_log_entry_log.famfs_sa }
+            },
+            famfs_log_entry_type::FAMFS_LOG_SYMLINK => LogEntry::Symlink {
+                symlink: unsafe { &self.famfs_log_entry_log.famfs_sl }
+            },
+            famfs_log_entry_type::FAMFS_LOG_RESIZE => LogEntry::Resize {
+                file_meta: unsafe { &self.famfs_log_entry_log.famfs_fm }
+            },
             famfs_log_entry_type::FAMFS_LOG_INVALID => LogEntry::Invalid,
         }
     }
@@ -340,6 +1055,71 @@ pub struct famfs_log {
 }
 
 impl famfs_log {
+    // Covers the fields before famfs_log_crc
+    pub fn generate_crc(&self) -> u32 {
+        let mut crc = crc32fast::Hasher::new();
+
+        crc.update(&self.encode()[..offset_of!(famfs_log, famfs_log_crc)]);
+
+        crc.finalize()
+    }
+
+    pub fn regenerate_crc(&mut self) {
+        self.famfs_log_crc = self.generate_crc();
+    }
+
+    pub fn check_crc(&self) -> bool {
+        self.famfs_log_crc == self.generate_crc()
+    }
+
+    // The header only, entries are encoded individually
+    pub fn encode(&self) -> [u8; size_of::<famfs_log>()] {
+        let mut buf = [0; size_of::<famfs_log>()];
+
+        put_u64(&mut buf, offset_of!(famfs_log, famfs_log_magic), self.famfs_log_magic);
+        put_u64(&mut buf, offset_of!(famfs_log, famfs_log_len), self.famfs_log_len);
+        put_u64(&mut buf, offset_of!(famfs_log, famfs_log_last_index), self.famfs_log_last_index);
+        put_u32(&mut buf, offset_of!(famfs_log, famfs_log_crc), self.famfs_log_crc);
+        put_u64(&mut buf, offset_of!(famfs_log, famfs_log_next_seqnum), self.famfs_log_next_seqnum);
+        put_u64(&mut buf, offset_of!(famfs_log, famfs_log_next_index), self.famfs_log_next_index);
+
+        buf
+    }
+
+    // Returns None if buf is too short to hold a log header
+    pub fn decode(buf: &[u8]) -> Option<famfs_log> {
+        if buf.len() < size_of::<famfs_log>() {
+            return None;
+        }
+
+        Some(famfs_log {
+            famfs_log_magic: get_u64(buf, offset_of!(famfs_log, famfs_log_magic)),
+            famfs_log_len: get_u64(buf, offset_of!(famfs_log, famfs_log_len)),
+            famfs_log_last_index: get_u64(buf, offset_of!(famfs_log, famfs_log_last_index)),
+            famfs_log_crc: get_u32(buf, offset_of!(famfs_log, famfs_log_crc)),
+            famfs_log_next_seqnum: get_u64(buf, offset_of!(famfs_log, famfs_log_next_seqnum)),
+            famfs_log_next_index: get_u64(buf, offset_of!(famfs_log, famfs_log_next_index)),
+        })
+    }
+
+    // Lays down an empty log of log_len bytes (including this header)
+    pub(crate) fn format(&mut self, log_len: u64, iface: &mut dyn FamfsMetadataInterface) {
+        let nentries = Self::entry_count(log_len);
+
+        let mut log = famfs_log {
+            famfs_log_magic: FAMFS_LOG_MAGIC,
+            famfs_log_len: log_len,
+            famfs_log_last_index: nentries - 1,
+            famfs_log_crc: 0,
+            famfs_log_next_seqnum: 0,
+            famfs_log_next_index: 0,
+        };
+        log.regenerate_crc();
+        unsafe { std::ptr::copy_nonoverlapping(log.encode().as_ptr(), (self as *mut Self).cast::<u8>(), size_of::<Self>()) };
+
+        iface.mark_dirty(DirtyPages::log(0, size_of::<famfs_log>()));
+    }
+
     pub fn check_log(&self) -> bool {
         if self.famfs_log_magic != FAMFS_LOG_MAGIC {
             return false;
@@ -390,36 +1170,71 @@ impl famfs_log {
         self.famfs_log_next_index > self.famfs_log_last_index
     }
 
-    // not thread safe or any other kind of safe
-    pub unsafe fn append_entry(&mut self, mut entry: famfs_log_entry) {
+    /// Stamps `entry` with the next seqnum and appends it to the log.
+    ///
+    /// The entry is made durable before the index that publishes it, so a
+    /// crash (or a client replaying concurrently) never sees a torn entry.
+    /// If the entry can't be persisted it isn't published and the commit's
+    /// errno comes back. If the header can't be, the entry is in the log
+    /// but may not survive a crash, and the errno comes back too.
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it for as long as this
+    /// runs, and the caller must hold the log lock so nothing else appends
+    /// at the same time. Nothing here checks for room, the caller has to
+    /// check `log_full` first.
+    pub unsafe fn append_entry(&mut self, mut entry: famfs_log_entry, iface: &mut dyn FamfsMetadataInterface) -> Result<(), i64> {
         entry.famfs_log_entry_seqnum = self.famfs_log_next_seqnum;
-        unsafe { *self.get_entry_mut(self.famfs_log_next_index as usize) = entry };
-        self.famfs_log_next_index+=1;
+        entry.regenerate_crc();
+
+        // store the encoding rather than the struct so padding goes to
+        // the media as zeroes
+        let index = self.famfs_log_next_index as usize;
+        unsafe { std::ptr::copy_nonoverlapping(entry.encode().as_ptr(), self.get_entry_mut(index).cast::<u8>(), size_of::<famfs_log_entry>()) };
+        iface.mark_dirty(DirtyPages::log(Self::entry_offset(index), size_of::<famfs_log_entry>()));
+        iface.commit().map_err(|err| -(err.raw_os_error().unwrap_or(libc::EIO) as i64))?;
+
         self.famfs_log_next_seqnum+=1;
+        fence(Ordering::Release);
+        unsafe { std::ptr::addr_of_mut!(self.famfs_log_next_index).write_volatile(self.famfs_log_next_index + 1) };
+        iface.mark_dirty(DirtyPages::log(std::mem::offset_of!(famfs_log, famfs_log_next_seqnum), size_of::<u64>()));
+        iface.mark_dirty(DirtyPages::log(std::mem::offset_of!(famfs_log, famfs_log_next_index), size_of::<u64>()));
+        iface.commit().map_err(|err| -(err.raw_os_error().unwrap_or(libc::EIO) as i64))
+    }
+
+    // number of entries that fit in a log of log_len bytes
+    pub fn entry_count(log_len: u64) -> u64 {
+        log_len.saturating_sub(size_of::<famfs_log>() as u64) / size_of::<famfs_log_entry>() as u64
+    }
+
+    // byte offset of entry `i` from the start of the log header
+    pub fn entry_offset(i: usize) -> usize {
+        size_of::<famfs_log>() + i * size_of::<famfs_log_entry>()
     }
 
     // not reentrant
+    #[allow(clippy::too_many_arguments)]
     pub unsafe fn log_file_create(
         &mut self, 
         fmap: &famfs_log_fmap, 
-        path: &Path,
+        path: &RelPath,
         mode_t: u32,
         uid_t: u32,
         gid_t: u32,
-        size: u64
+        size: u64,
+        flags: u32,
+        iface: &mut dyn FamfsMetadataInterface
     ) -> Result<(), i64> {
-        let path_bytes = path.as_os_str().as_encoded_bytes();
-        let mut relpath: [u8; FAMFS_MAX_PATHLEN] = [0; 80];
-        relpath[..path_bytes.len()].copy_from_slice(path_bytes);
+        let relpath = path.to_relpath();
 
         let mut le = famfs_log_entry {
             famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
-            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_FILE,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_FILE as u32,
             famfs_log_entry_log: famfs_log_entry_union {
                 famfs_fm: ManuallyDrop::new(
                     famfs_log_file_meta { 
                         fm_size:  size, 
-                        fm_flags: FAMFS_FM_ALL_HOSTS_RW, // hard coded for now
+                        fm_flags: flags,
                         fm_uid: uid_t, 
                         fm_gid: gid_t, 
                         fm_mode: mode_t, 
@@ -436,7 +1251,227 @@ impl famfs_log {
         if self.log_full() {
             return Err(0);
         }
-        unsafe { self.append_entry(le); }
+        unsafe { self.append_entry(le, iface)?; }
+
+        Ok(())
+    }
+
+    /// Logs a directory at `path`, fails with `Err(0)` if the log is full
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it and the caller must
+    /// hold the log lock, see `append_entry`
+    pub unsafe fn log_mkdir(
+        &mut self,
+        path: &RelPath,
+        mode_t: u32,
+        uid_t: u32,
+        gid_t: u32,
+        iface: &mut dyn FamfsMetadataInterface
+    ) -> Result<(), i64> {
+        let relpath = path.to_relpath();
+
+        // start from zeroes, the mkdir is smaller than the union
+        let mut famfs_log_entry_log = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+        famfs_log_entry_log.famfs_md = ManuallyDrop::new(famfs_log_mkdir {
+            md_uid: uid_t,
+            md_gid: gid_t,
+            md_mode: mode_t,
+            md_relpath: relpath
+        });
+
+        let le = famfs_log_entry {
+            famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_MKDIR as u32,
+            famfs_log_entry_log,
+            famfs_log_entry_crc: 0,
+            famfs_pad: 0
+        };
+
+        if self.log_full() {
+            return Err(0);
+        }
+        unsafe { self.append_entry(le, iface)?; }
+
+        Ok(())
+    }
+
+    /// Logs a symlink at `path` pointing at `target`, which has to fit in
+    /// FAMFS_MAX_PATHLEN with its NUL terminator (-ENAMETOOLONG otherwise).
+    /// Fails with `Err(0)` if the log is full.
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it and the caller must
+    /// hold the log lock, see `append_entry`
+    pub unsafe fn log_symlink(
+        &mut self,
+        path: &RelPath,
+        target: &Path,
+        uid_t: u32,
+        gid_t: u32,
+        iface: &mut dyn FamfsMetadataInterface
+    ) -> Result<(), i64> {
+        let target = target.as_os_str().as_bytes();
+        if target.len() >= FAMFS_MAX_PATHLEN {
+            return Err(-(libc::ENAMETOOLONG as i64));
+        }
+        let mut sl_target = [0; FAMFS_MAX_PATHLEN];
+        sl_target[..target.len()].copy_from_slice(target);
+
+        let mut famfs_log_entry_log = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+        famfs_log_entry_log.famfs_sl = ManuallyDrop::new(famfs_log_symlink {
+            sl_uid: uid_t,
+            sl_gid: gid_t,
+            sl_relpath: path.to_relpath(),
+            sl_target
+        });
+
+        let le = famfs_log_entry {
+            famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_SYMLINK as u32,
+            famfs_log_entry_log,
+            famfs_log_entry_crc: 0,
+            famfs_pad: 0
+        };
+
+        if self.log_full() {
+            return Err(0);
+        }
+        unsafe { self.append_entry(le, iface)?; }
+
+        Ok(())
+    }
+
+    /// Logs a new mode and owner for the file or directory at `path`,
+    /// fails with `Err(0)` if the log is full
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it and the caller must
+    /// hold the log lock, see `append_entry`
+    pub unsafe fn log_setattr(
+        &mut self,
+        path: &RelPath,
+        mode_t: u32,
+        uid_t: u32,
+        gid_t: u32,
+        iface: &mut dyn FamfsMetadataInterface
+    ) -> Result<(), i64> {
+        let mut famfs_log_entry_log = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+        famfs_log_entry_log.famfs_sa = ManuallyDrop::new(famfs_log_setattr {
+            sa_uid: uid_t,
+            sa_gid: gid_t,
+            sa_mode: mode_t,
+            sa_relpath: path.to_relpath()
+        });
+
+        let le = famfs_log_entry {
+            famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_SETATTR as u32,
+            famfs_log_entry_log,
+            famfs_log_entry_crc: 0,
+            famfs_pad: 0
+        };
+
+        if self.log_full() {
+            return Err(0);
+        }
+        unsafe { self.append_entry(le, iface)?; }
+
+        Ok(())
+    }
+
+    /// Logs the move of `path`, and everything under it, to `new_path`.
+    /// Fails with `Err(0)` if the log is full.
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it and the caller must
+    /// hold the log lock, see `append_entry`
+    pub unsafe fn log_rename(
+        &mut self,
+        path: &RelPath,
+        new_path: &RelPath,
+        iface: &mut dyn FamfsMetadataInterface
+    ) -> Result<(), i64> {
+        let mut famfs_log_entry_log = famfs_log_entry_union { famfs_raw: [0; size_of::<famfs_log_file_meta>()] };
+        famfs_log_entry_log.famfs_rn = ManuallyDrop::new(famfs_log_rename {
+            rn_relpath: path.to_relpath(),
+            rn_new_relpath: new_path.to_relpath()
+        });
+
+        let le = famfs_log_entry {
+            famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_RENAME as u32,
+            famfs_log_entry_log,
+            famfs_log_entry_crc: 0,
+            famfs_pad: 0
+        };
+
+        if self.log_full() {
+            return Err(0);
+        }
+        unsafe { self.append_entry(le, iface)?; }
+
+        Ok(())
+    }
+
+    /// Logs the removal of the file described by `file_meta`, which has to
+    /// be the metadata of the file as it was logged, at the path it has
+    /// now. Fails with `Err(0)` if the log is full.
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it and the caller must
+    /// hold the log lock, see `append_entry`. Releasing the file's space is
+    /// up to the caller.
+    pub unsafe fn log_delete(
+        &mut self,
+        file_meta: &famfs_log_file_meta,
+        iface: &mut dyn FamfsMetadataInterface
+    ) -> Result<(), i64> {
+        let le = famfs_log_entry {
+            famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_DELETE as u32,
+            famfs_log_entry_log: famfs_log_entry_union {
+                famfs_fm: ManuallyDrop::new(*file_meta)
+            },
+            famfs_log_entry_crc: 0,
+            famfs_pad: 0
+        };
+
+        if self.log_full() {
+            return Err(0);
+        }
+        unsafe { self.append_entry(le, iface)?; }
+
+        Ok(())
+    }
+
+    /// Logs new metadata for a file in a single entry, `file_meta` has to
+    /// be the whole file as it is to be, at the path it has now. Fails
+    /// with `Err(0)` if the log is full.
+    ///
+    /// # Safety
+    /// The log's entries must be mapped right after it and the caller must
+    /// hold the log lock, see `append_entry`. The new map has to keep the
+    /// file's old extents, the space it adds is claimed by the caller.
+    pub unsafe fn log_resize(
+        &mut self,
+        file_meta: &famfs_log_file_meta,
+        iface: &mut dyn FamfsMetadataInterface
+    ) -> Result<(), i64> {
+        let le = famfs_log_entry {
+            famfs_log_entry_seqnum: self.famfs_log_next_seqnum,
+            famfs_log_entry_type: famfs_log_entry_type::FAMFS_LOG_RESIZE as u32,
+            famfs_log_entry_log: famfs_log_entry_union {
+                famfs_fm: ManuallyDrop::new(*file_meta)
+            },
+            famfs_log_entry_crc: 0,
+            famfs_pad: 0
+        };
+
+        if self.log_full() {
+            return Err(0);
+        }
+        unsafe { self.append_entry(le, iface)?; }
 
         Ok(())
     }
@@ -458,4 +1493,98 @@ impl famfs_interleave_param {
     ) -> bool {
         todo!()
     }
-}
\ No newline at end of file
+}
+// Layout of the on-media structures, these must match the C famfs headers
+const _: () = {
+    assert!(size_of::<famfs_daxdev>() == 88);
+    assert!(offset_of!(famfs_daxdev, dd_size) == 0);
+    assert!(offset_of!(famfs_daxdev, dd_uuid) == 8);
+    assert!(offset_of!(famfs_daxdev, daxdev) == 24);
+
+    assert!(size_of::<famfs_superblock>() == 200);
+    assert!(offset_of!(famfs_superblock, ts_magic) == 0);
+    assert!(offset_of!(famfs_superblock, ts_version) == 8);
+    assert!(offset_of!(famfs_superblock, ts_log_offset) == 16);
+    assert!(offset_of!(famfs_superblock, ts_log_len) == 24);
+    assert!(offset_of!(famfs_superblock, ts_alloc_unit) == 32);
+    assert!(offset_of!(famfs_superblock, ts_omf_ver_major) == 40);
+    assert!(offset_of!(famfs_superblock, ts_omf_ver_minor) == 44);
+    assert!(offset_of!(famfs_superblock, ts_uuid) == 48);
+    assert!(offset_of!(famfs_superblock, ts_dev_uuid) == 64);
+    assert!(offset_of!(famfs_superblock, ts_system_uuid) == 80);
+    assert!(offset_of!(famfs_superblock, ts_crc) == 96);
+    assert!(offset_of!(famfs_superblock, ts_pad) == 100);
+    assert!(offset_of!(famfs_superblock, ts_sb_flags) == 104);
+    assert!(offset_of!(famfs_superblock, ts_daxdev) == 112);
+
+    assert!(size_of::<famfs_simple_extent>() == 24);
+    assert!(offset_of!(famfs_simple_extent, se_devindex) == 0);
+    assert!(offset_of!(famfs_simple_extent, se_offset) == 8);
+    assert!(offset_of!(famfs_simple_extent, se_len) == 16);
+
+    assert!(size_of::<famfs_interleaved_ext>() == 400);
+    assert!(offset_of!(famfs_interleaved_ext, ie_nstrips) == 0);
+    assert!(offset_of!(famfs_interleaved_ext, ie_chunk_size) == 8);
+    assert!(offset_of!(famfs_interleaved_ext, ie_strips) == 16);
+
+    assert!(size_of::<famfs_log_fmap_union_simple_extent>() == 392);
+    assert!(offset_of!(famfs_log_fmap_union_simple_extent, fmap_nextents) == 0);
+    assert!(offset_of!(famfs_log_fmap_union_simple_extent, se) == 8);
+
+    assert!(size_of::<famfs_log_fmap_union_interleaved_extent>() == 408);
+    assert!(offset_of!(famfs_log_fmap_union_interleaved_extent, fmap_niext) == 0);
+    assert!(offset_of!(famfs_log_fmap_union_interleaved_extent, se) == 8);
+
+    assert!(size_of::<famfs_log_fmap>() == 416);
+    assert!(offset_of!(famfs_log_fmap, fmap_ext_type) == 0);
+    assert!(offset_of!(famfs_log_fmap, inner) == 8);
+
+    assert!(size_of::<famfs_log_file_meta>() == 520);
+    assert!(offset_of!(famfs_log_file_meta, fm_size) == 0);
+    assert!(offset_of!(famfs_log_file_meta, fm_flags) == 8);
+    assert!(offset_of!(famfs_log_file_meta, fm_uid) == 12);
+    assert!(offset_of!(famfs_log_file_meta, fm_gid) == 16);
+    assert!(offset_of!(famfs_log_file_meta, fm_mode) == 20);
+    assert!(offset_of!(famfs_log_file_meta, fm_relpath) == 24);
+    assert!(offset_of!(famfs_log_file_meta, fm_fmap) == 104);
+
+    assert!(size_of::<famfs_log_mkdir>() == 92);
+    assert!(offset_of!(famfs_log_mkdir, md_uid) == 0);
+    assert!(offset_of!(famfs_log_mkdir, md_gid) == 4);
+    assert!(offset_of!(famfs_log_mkdir, md_mode) == 8);
+    assert!(offset_of!(famfs_log_mkdir, md_relpath) == 12);
+
+    // not in the C headers, these are ours and the C tools skip them
+    assert!(size_of::<famfs_log_rename>() == 160);
+    assert!(offset_of!(famfs_log_rename, rn_relpath) == 0);
+    assert!(offset_of!(famfs_log_rename, rn_new_relpath) == 80);
+
+    assert!(size_of::<famfs_log_setattr>() == 92);
+    assert!(offset_of!(famfs_log_setattr, sa_uid) == 0);
+    assert!(offset_of!(famfs_log_setattr, sa_gid) == 4);
+    assert!(offset_of!(famfs_log_setattr, sa_mode) == 8);
+    assert!(offset_of!(famfs_log_setattr, sa_relpath) == 12);
+
+    assert!(size_of::<famfs_log_symlink>() == 168);
+    assert!(offset_of!(famfs_log_symlink, sl_uid) == 0);
+    assert!(offset_of!(famfs_log_symlink, sl_gid) == 4);
+    assert!(offset_of!(famfs_log_symlink, sl_relpath) == 8);
+    assert!(offset_of!(famfs_log_symlink, sl_target) == 88);
+
+    assert!(size_of::<famfs_log_entry_union>() == 520);
+
+    assert!(size_of::<famfs_log_entry>() == 544);
+    assert!(offset_of!(famfs_log_entry, famfs_log_entry_seqnum) == 0);
+    assert!(offset_of!(famfs_log_entry, famfs_log_entry_type) == 8);
+    assert!(offset_of!(famfs_log_entry, famfs_log_entry_log) == 16);
+    assert!(offset_of!(famfs_log_entry, famfs_log_entry_crc) == 536);
+    assert!(offset_of!(famfs_log_entry, famfs_pad) == 540);
+
+    assert!(size_of::<famfs_log>() == 48);
+    assert!(offset_of!(famfs_log, famfs_log_magic) == 0);
+    assert!(offset_of!(famfs_log, famfs_log_len) == 8);
+    assert!(offset_of!(famfs_log, famfs_log_last_index) == 16);
+    assert!(offset_of!(famfs_log, famfs_log_crc) == 24);
+    assert!(offset_of!(famfs_log, famfs_log_next_seqnum) == 32);
+    assert!(offset_of!(famfs_log, famfs_log_next_index) == 40);
+};
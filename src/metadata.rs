@@ -0,0 +1,118 @@
+use crate::access::node_attrs;
+use crate::meta::{famfs_log, famfs_log_entry, famfs_simple_extent, LogEntry};
+use crate::namespace::{Node, NodeKind};
+
+/// What the log says about a file, directory or symlink, decoded out of
+/// its entry so callers don't depend on the on-media layout. Directories
+/// have no length, symlinks are as long as their target, and neither has
+/// flags or extents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FamfsMetadata {
+    kind: NodeKind,
+    len: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    flags: u32,
+    extents: Vec<famfs_simple_extent>,
+    seqnum: u64
+}
+
+impl FamfsMetadata {
+    // None for entries that don't create a file, directory or symlink
+    pub fn from_entry(entry: &famfs_log_entry) -> Option<FamfsMetadata> {
+        match entry.get_entry_type() {
+            LogEntry::File { file_meta } | LogEntry::Resize { file_meta } => Some(FamfsMetadata {
+                kind: NodeKind::File,
+                len: file_meta.fm_size,
+                mode: file_meta.fm_mode,
+                uid: file_meta.fm_uid,
+                gid: file_meta.fm_gid,
+                flags: file_meta.fm_flags,
+                extents: file_meta.extent_list(),
+                seqnum: entry.seqnum(),
+            }),
+            LogEntry::MakeDir { dir_meta } => Some(FamfsMetadata {
+                kind: NodeKind::Dir,
+                len: 0,
+                mode: dir_meta.mode(),
+                uid: dir_meta.uid(),
+                gid: dir_meta.gid(),
+                flags: 0,
+                extents: Vec::new(),
+                seqnum: entry.seqnum(),
+            }),
+            LogEntry::Symlink { symlink } => Some(FamfsMetadata {
+                kind: NodeKind::Symlink,
+                len: symlink.target().as_os_str().len() as u64,
+                mode: 0o777,
+                uid: symlink.uid(),
+                gid: symlink.gid(),
+                flags: 0,
+                extents: Vec::new(),
+                seqnum: entry.seqnum(),
+            }),
+            _ => None,
+        }
+    }
+
+    // What a namespace node says now, with the mode and owner of its
+    // last setattr entry
+    pub fn from_node(log: &famfs_log, node: &Node) -> Option<FamfsMetadata> {
+        let mut metadata = FamfsMetadata::from_entry(unsafe { log.get_entry_ref(node.index as usize) })?;
+        (_, metadata.mode, metadata.uid, metadata.gid) = node_attrs(log, node);
+        Some(metadata)
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == NodeKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == NodeKind::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == NodeKind::Symlink
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Permission bits only, the log doesn't store a file type
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    // FAMFS_FM_* access flags
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    // Every extent backing the file, interleaved strips in order
+    pub fn extents(&self) -> &[famfs_simple_extent] {
+        &self.extents
+    }
+
+    // Seqnums grow with each log entry, so they order creations
+    pub fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Bound;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{fence, Ordering};
+use std::time::Duration;
+
+use crate::cache::Coherence;
+use crate::internal::famfs_log_stats;
+use crate::meta::{famfs_log, famfs_log_entry, LogEntry};
+use crate::relpath::RelPath;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Dir,
+    Symlink
+}
+
+// How many symlinks a lookup follows before giving up with ELOOP, the
+// same limit as Linux
+pub const FAMFS_MAX_SYMLINKS: usize = 40;
+
+// A namespace node only remembers where it came from in the log,
+// the metadata itself stays in the (shared) log entry. The mode and
+// owner come from the last setattr entry for the node, if there is one.
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub index: u64,
+    pub seqnum: u64,
+    pub attr_index: Option<u64>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogEvent {
+    Create { path: PathBuf, index: u64, seqnum: u64 },
+    Mkdir { path: PathBuf, index: u64, seqnum: u64 },
+    Delete { path: PathBuf, index: u64, seqnum: u64 },
+    Rename { from: PathBuf, to: PathBuf, index: u64, seqnum: u64 },
+    SetAttr { path: PathBuf, index: u64, seqnum: u64 },
+    Symlink { path: PathBuf, index: u64, seqnum: u64 },
+    Resize { path: PathBuf, index: u64, seqnum: u64 }
+}
+
+// relpaths are stored NUL padded in a fixed size buffer. They're
+// normalized like lookups are, paths that aren't valid (only older
+// writers could have logged them) are kept as they are.
+pub(crate) fn relpath_to_path(relpath: &[u8]) -> PathBuf {
+    let len = relpath.iter().position(|b| *b == 0).unwrap_or(relpath.len());
+    let path = Path::new(OsStr::from_bytes(&relpath[..len]));
+
+    match RelPath::new(path) {
+        Ok(relpath) => relpath.as_path().to_path_buf(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+// The names along a path, with .. kept and the root and . dropped
+fn components(path: &Path) -> impl DoubleEndedIterator<Item = OsString> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name.to_os_string()),
+        Component::ParentDir => Some(OsString::from("..")),
+        Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+    })
+}
+
+/// The namespace built by replaying the log.
+///
+/// The namespace remembers how far into the log it has been applied, so a
+/// client sharing the log with a master can call `apply_new` to pick up
+/// only the entries appended since the last call.
+#[derive(Default, Clone)]
+pub struct Namespace {
+    nodes: BTreeMap<PathBuf, Node>,
+    next_index: u64,
+    last_seqnum: Option<u64>,
+    stats: famfs_log_stats,
+    coherence: Coherence // whether log reads drop cached lines first
+}
+
+impl Namespace {
+    pub fn new() -> Namespace {
+        Namespace::default()
+    }
+
+    // An empty namespace that reads the log as coherence says
+    pub fn with_coherence(coherence: Coherence) -> Namespace {
+        Namespace { coherence, ..Namespace::default() }
+    }
+
+    pub fn coherence(&self) -> Coherence {
+        self.coherence
+    }
+
+    // Only reads from here on are affected, what was applied stays
+    pub fn set_coherence(&mut self, coherence: Coherence) {
+        self.coherence = coherence;
+    }
+
+    /// Builds the namespace from every entry currently in the log
+    ///
+    /// # Safety
+    /// `log` must point to a mapped log header followed by its entries
+    pub unsafe fn replay(log: *const famfs_log) -> Namespace {
+        let mut ns = Namespace::new();
+        unsafe { ns.apply_new(log) };
+        ns
+    }
+
+    /// Applies the entries appended to the log since the last call and
+    /// returns what changed. If the log was reinitialized (its index went
+    /// backwards) the namespace is rebuilt from the start of the log.
+    ///
+    /// # Safety
+    /// `log` must point to a mapped log header followed by its entries
+    pub unsafe fn apply_new(&mut self, log: *const famfs_log) -> Vec<LogEvent> {
+        // the header is rewritten by the master on every append, so make
+        // sure we don't read a stale copy out of our own cache
+        self.coherence.invalidate(log.cast::<u8>(), size_of::<famfs_log>());
+
+        let (log_next_index, last_index) = unsafe {
+            (
+                std::ptr::addr_of!((*log).famfs_log_next_index).read_volatile(),
+                std::ptr::addr_of!((*log).famfs_log_last_index).read_volatile()
+            )
+        };
+        fence(Ordering::Acquire);
+
+        if log_next_index < self.next_index {
+            *self = Namespace::with_coherence(self.coherence);
+        }
+
+        // a corrupt header can claim the last index is u64::MAX
+        let end = last_index.checked_add(1).map_or(log_next_index, |len| std::cmp::min(log_next_index, len));
+        let log = unsafe { &*log };
+        let mut events = Vec::new();
+
+        for i in self.next_index..end {
+            let entry = unsafe { log.get_entry_ref(i as usize) };
+            self.coherence.invalidate((entry as *const famfs_log_entry).cast::<u8>(), size_of::<famfs_log_entry>());
+
+            if let Some(event) = self.apply_entry(i, entry) {
+                events.push(event);
+            }
+        }
+
+        if end > self.next_index {
+            self.next_index = end;
+        }
+
+        events
+    }
+
+    // Applies the entry at `index`, for walks over the log (like the
+    // bitmap's) that need the namespace as it was at each entry
+    pub(crate) fn apply_entry(&mut self, index: u64, entry: &famfs_log_entry) -> Option<LogEvent> {
+        self.stats.n_entries += 1;
+
+        if !entry.check_crc() {
+            self.stats.bad_entries += 1;
+            return None;
+        }
+
+        let seqnum = entry.seqnum();
+        self.last_seqnum = Some(seqnum);
+
+        match entry.get_entry_type() {
+            LogEntry::File { file_meta } => {
+                let path = relpath_to_path(&file_meta.fm_relpath);
+                self.stats.f_logged += 1;
+                self.nodes.insert(path.clone(), Node { kind: NodeKind::File, index, seqnum, attr_index: None });
+
+                Some(LogEvent::Create { path, index, seqnum })
+            },
+            LogEntry::MakeDir { dir_meta } => {
+                let path = relpath_to_path(&dir_meta.md_relpath);
+                self.stats.d_logged += 1;
+                self.nodes.insert(path.clone(), Node { kind: NodeKind::Dir, index, seqnum, attr_index: None });
+
+                Some(LogEvent::Mkdir { path, index, seqnum })
+            },
+            LogEntry::Delete { file_meta } => {
+                let path = relpath_to_path(&file_meta.fm_relpath);
+                self.nodes.remove(&path);
+
+                Some(LogEvent::Delete { path, index, seqnum })
+            },
+            LogEntry::Rename { rename } => {
+                let from = relpath_to_path(&rename.rn_relpath);
+                let to = relpath_to_path(&rename.rn_new_relpath);
+                self.rename(&from, &to);
+
+                Some(LogEvent::Rename { from, to, index, seqnum })
+            },
+            LogEntry::Symlink { symlink } => {
+                let path = relpath_to_path(&symlink.sl_relpath);
+                self.stats.s_logged += 1;
+                self.nodes.insert(path.clone(), Node { kind: NodeKind::Symlink, index, seqnum, attr_index: None });
+
+                Some(LogEvent::Symlink { path, index, seqnum })
+            },
+            LogEntry::Resize { file_meta } => {
+                let path = relpath_to_path(&file_meta.fm_relpath);
+                // the entry carries the file's mode and owner, setattrs
+                // logged before it included
+                self.nodes.insert(path.clone(), Node { kind: NodeKind::File, index, seqnum, attr_index: None });
+
+                Some(LogEvent::Resize { path, index, seqnum })
+            },
+            LogEntry::SetAttr { attr } => {
+                let path = relpath_to_path(&attr.sa_relpath);
+                let node = self.nodes.get_mut(&path)?;
+                node.attr_index = Some(index);
+
+                Some(LogEvent::SetAttr { path, index, seqnum })
+            },
+            LogEntry::Invalid => {
+                self.stats.bad_entries += 1;
+                None
+            }
+        }
+    }
+
+    // Moves from and everything below it under to. The nodes keep the
+    // entries that created them.
+    fn rename(&mut self, from: &Path, to: &Path) {
+        let Some(node) = self.nodes.remove(from) else {
+            return;
+        };
+        let moved: Vec<PathBuf> = self.descendants(from).map(|(path, _)| path.to_path_buf()).collect();
+
+        self.nodes.insert(to.to_path_buf(), node);
+        for path in moved {
+            if let Some(node) = self.nodes.remove(&path) {
+                let rest = path.strip_prefix(from).unwrap_or(&path);
+                self.nodes.insert(to.join(rest), node);
+            }
+        }
+    }
+
+    // Paths are normalized first, so "a/./b/" finds "a/b" but "a" never
+    // finds "abc"
+    pub fn lookup(&self, path: &Path) -> Option<&Node> {
+        let path = RelPath::new(path).ok()?;
+        self.nodes.get(path.as_path())
+    }
+
+    /// Finds the path `path` leads to, following the symlinks in every
+    /// component but the last, and in the last too with `follow`. Link
+    /// targets are read out of `log` and resolved from the link's
+    /// directory, or from the root if they start with /. Fails with
+    /// -ENOENT, -ENOTDIR, or -ELOOP after FAMFS_MAX_SYMLINKS links.
+    pub fn resolve(&self, log: &famfs_log, path: &Path, follow: bool) -> Result<PathBuf, i64> {
+        self.resolve_with(log, path, follow, |_| Ok(()))
+    }
+
+    /// Like `resolve`, but every directory the lookup goes through is
+    /// passed to `search` first, which can fail the lookup. That includes
+    /// the directories on the way to a link and not only those on the
+    /// resolved path.
+    pub fn resolve_with(
+        &self,
+        log: &famfs_log,
+        path: &Path,
+        follow: bool,
+        mut search: impl FnMut(&Node) -> Result<(), i64>
+    ) -> Result<PathBuf, i64> {
+        let mut pending: VecDeque<OsString> = components(path).collect();
+        let mut resolved = PathBuf::new();
+        let mut links = 0;
+
+        while let Some(name) = pending.pop_front() {
+            if name == ".." {
+                resolved.pop();
+                continue;
+            }
+
+            let candidate = resolved.join(&name);
+            let node = self.nodes.get(&candidate).ok_or(-(libc::ENOENT as i64))?;
+
+            if node.kind == NodeKind::Symlink && (follow || !pending.is_empty()) {
+                links += 1;
+                if links > FAMFS_MAX_SYMLINKS {
+                    return Err(-(libc::ELOOP as i64));
+                }
+
+                let LogEntry::Symlink { symlink } = unsafe { log.get_entry_ref(node.index as usize) }.get_entry_type() else {
+                    unreachable!("symlink nodes come from symlink entries");
+                };
+                if symlink.target().has_root() {
+                    resolved.clear();
+                }
+                for name in components(symlink.target()).rev() {
+                    pending.push_front(name);
+                }
+                continue;
+            }
+
+            if !pending.is_empty() {
+                if node.kind != NodeKind::Dir {
+                    return Err(-(libc::ENOTDIR as i64));
+                }
+                search(node)?;
+            }
+            resolved = candidate;
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Node)> {
+        self.nodes.iter().map(|(path, node)| (path.as_path(), node))
+    }
+
+    // Everything below dir (the empty path for the root), depth first
+    // with each directory ahead of its contents. Paths order component by
+    // component, so a directory's descendants follow it in the map.
+    pub fn descendants<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = (&'a Path, &'a Node)> + 'a {
+        let root = dir.as_os_str().is_empty();
+
+        self.nodes
+            .range::<Path, _>((Bound::Excluded(dir), Bound::Unbounded))
+            .take_while(move |(path, _)| root || path.starts_with(dir))
+            .map(|(path, node)| (path.as_path(), node))
+    }
+
+    pub fn children<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = (&'a Path, &'a Node)> + 'a {
+        self.descendants(dir).filter(move |(path, _)| path.parent() == Some(dir))
+    }
+
+    /// The index of the first log entry not yet applied
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    /// The seqnum of the last entry applied, if any
+    pub fn last_seqnum(&self) -> Option<u64> {
+        self.last_seqnum
+    }
+
+    pub fn stats(&self) -> &famfs_log_stats {
+        &self.stats
+    }
+}
+
+/// Polls a shared log for new entries.
+///
+/// `poll` never blocks, iterating the watcher blocks until the next event,
+/// sleeping `interval` between checks of the log header.
+pub struct LogWatcher {
+    log: *const famfs_log,
+    namespace: Namespace,
+    interval: Duration,
+    pending: VecDeque<LogEvent>
+}
+
+impl LogWatcher {
+    /// Only entries after `namespace.next_index()` are reported
+    ///
+    /// # Safety
+    /// `log` must point to a mapped log header followed by its entries
+    /// and stay mapped for the lifetime of the watcher
+    pub unsafe fn new(log: *const famfs_log, namespace: Namespace, interval: Duration) -> LogWatcher {
+        LogWatcher {
+            log,
+            namespace,
+            interval,
+            pending: VecDeque::new()
+        }
+    }
+
+    pub fn poll(&mut self) -> Vec<LogEvent> {
+        let mut events: Vec<LogEvent> = self.pending.drain(..).collect();
+        events.extend(unsafe { self.namespace.apply_new(self.log) });
+        events
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    pub fn into_namespace(self) -> Namespace {
+        self.namespace
+    }
+}
+
+impl Iterator for LogWatcher {
+    type Item = LogEvent;
+
+    fn next(&mut self) -> Option<LogEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+
+            let events = unsafe { self.namespace.apply_new(self.log) };
+            if events.is_empty() {
+                std::thread::sleep(self.interval);
+            }
+            self.pending.extend(events);
+        }
+    }
+}
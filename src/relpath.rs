@@ -0,0 +1,124 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::meta::FAMFS_MAX_PATHLEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelPathError {
+    Empty,
+    Absolute,
+    ParentDir, // a .. component
+    Nul,
+    TooLong
+}
+
+impl RelPathError {
+    // The errno log operations report for the error
+    pub fn errno(&self) -> i64 {
+        match self {
+            RelPathError::TooLong => -(libc::ENAMETOOLONG as i64),
+            _ => -(libc::EINVAL as i64),
+        }
+    }
+}
+
+impl fmt::Display for RelPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelPathError::Empty => write!(f, "empty path"),
+            RelPathError::Absolute => write!(f, "path is absolute"),
+            RelPathError::ParentDir => write!(f, "path has a .. component"),
+            RelPathError::Nul => write!(f, "path has a NUL byte"),
+            RelPathError::TooLong => write!(f, "path is longer than {} bytes", FAMFS_MAX_PATHLEN - 1),
+        }
+    }
+}
+
+impl std::error::Error for RelPathError {}
+
+/// A path relative to the root of a famfs, as the log stores it.
+///
+/// A RelPath is normalized (components are separated by a single /,
+/// repeated and trailing /s and `.` components are dropped) and always fits in a log
+/// entry with its NUL terminator, so two RelPaths naming the same file
+/// compare equal byte for byte.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RelPath {
+    bytes: Vec<u8>
+}
+
+impl RelPath {
+    pub fn new(path: &Path) -> Result<RelPath, RelPathError> {
+        let raw = path.as_os_str().as_bytes();
+        if raw.starts_with(b"/") {
+            return Err(RelPathError::Absolute);
+        }
+
+        let mut bytes = Vec::with_capacity(raw.len());
+
+        for component in raw.split(|b| *b == b'/') {
+            match component {
+                b"" | b"." => continue,
+                b".." => return Err(RelPathError::ParentDir),
+                _ if component.contains(&0) => return Err(RelPathError::Nul),
+                _ => {},
+            }
+
+            if !bytes.is_empty() {
+                bytes.push(b'/');
+            }
+            bytes.extend_from_slice(component);
+        }
+
+        if bytes.is_empty() {
+            return Err(RelPathError::Empty);
+        }
+        // the media needs room for the terminator
+        if bytes.len() >= FAMFS_MAX_PATHLEN {
+            return Err(RelPathError::TooLong);
+        }
+
+        Ok(RelPath { bytes })
+    }
+
+    // Reads a NUL terminated relpath out of a log entry
+    pub fn from_relpath(relpath: &[u8; FAMFS_MAX_PATHLEN]) -> Result<RelPath, RelPathError> {
+        let len = relpath.iter().position(|b| *b == 0).ok_or(RelPathError::TooLong)?;
+        RelPath::new(Path::new(OsStr::from_bytes(&relpath[..len])))
+    }
+
+    // The NUL padded form stored in log entries
+    pub fn to_relpath(&self) -> [u8; FAMFS_MAX_PATHLEN] {
+        let mut relpath = [0; FAMFS_MAX_PATHLEN];
+        relpath[..self.bytes.len()].copy_from_slice(&self.bytes);
+        relpath
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // None for top level paths, whose parent is the root
+    pub fn parent(&self) -> Option<RelPath> {
+        let slash = self.bytes.iter().rposition(|b| *b == b'/')?;
+        Some(RelPath { bytes: self.bytes[..slash].to_vec() })
+    }
+}
+
+impl AsRef<Path> for RelPath {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl fmt::Display for RelPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_path().display())
+    }
+}
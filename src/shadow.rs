@@ -0,0 +1,335 @@
+// The shadow filesystem mirrors the namespace as a directory tree under
+// <shadow_root>/root, directories are directories and each file is a yaml
+// document describing its log metadata. This is the format the C famfs
+// tools emit for famfs fuse:
+//
+// ---
+// file:
+//   path: dir/file
+//   size: 4096
+//   flags: 2
+//   mode: 0644
+//   uid: 0
+//   gid: 0
+//   nextents: 1
+//   simple_ext_list:
+//   - offset: 0xa00000
+//     length: 0x200000
+// ...
+//
+// Interleaved files have a striped_ext_list instead, each entry of which
+// has nstrips, chunk_size and a simple_ext_list of its strips.
+
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+
+use yaml_rust2::parser::{Event, EventReceiver, Parser};
+
+use crate::meta::{
+    famfs_interleaved_ext, famfs_log_file_meta, famfs_log_fmap, famfs_simple_extent, Extent,
+    FAMFS_MAX_INTERLEAVED_EXTENTS, FAMFS_MAX_SIMPLE_EXTENTS,
+};
+use crate::namespace::relpath_to_path;
+use crate::relpath::{RelPath, RelPathError};
+
+pub const SHADOW_ROOT_DIR: &str = "root";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShadowError {
+    Yaml(String),
+    Missing(&'static str),
+    BadValue(&'static str, String),
+    UnknownKey(String),
+    TooManyExtents,
+    Path(RelPathError)
+}
+
+impl fmt::Display for ShadowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShadowError::Yaml(err) => write!(f, "malformed yaml: {err}"),
+            ShadowError::Missing(key) => write!(f, "missing {key}"),
+            ShadowError::BadValue(key, value) => write!(f, "bad value for {key}: {value}"),
+            ShadowError::UnknownKey(key) => write!(f, "unknown key {key}"),
+            ShadowError::TooManyExtents => write!(f, "too many extents"),
+            ShadowError::Path(err) => write!(f, "bad path: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ShadowError {}
+
+// Where the yaml or directory for relpath lives under shadow_root
+pub fn shadow_path(shadow_root: &Path, relpath: &Path) -> PathBuf {
+    shadow_root.join(SHADOW_ROOT_DIR).join(relpath)
+}
+
+fn emit_extents(yaml: &mut String, indent: &str, extents: &[famfs_simple_extent]) {
+    writeln!(yaml, "{indent}simple_ext_list:").unwrap();
+    for se in extents {
+        writeln!(yaml, "{indent}- offset: {:#x}", se.se_offset).unwrap();
+        writeln!(yaml, "{indent}  length: {:#x}", se.se_len).unwrap();
+        // only multi device logs have anything but device 0
+        if se.se_devindex != 0 {
+            writeln!(yaml, "{indent}  devindex: {}", se.se_devindex).unwrap();
+        }
+    }
+}
+
+// Plain scalars can't hold everything a path can, quote the ones that
+// need it
+fn emit_path(yaml: &mut String, path: &str) {
+    let plain = !path.is_empty()
+        && path.bytes().all(|b| b.is_ascii_alphanumeric() || b"_./-+".contains(&b))
+        && !path.starts_with('-');
+
+    if plain {
+        yaml.push_str(path);
+        return;
+    }
+
+    yaml.push('"');
+    for c in path.chars() {
+        match c {
+            '"' => yaml.push_str("\\\""),
+            '\\' => yaml.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(yaml, "\\x{:02x}", c as u32).unwrap(),
+            c => yaml.push(c),
+        }
+    }
+    yaml.push('"');
+}
+
+pub fn emit_file_yaml(file_meta: &famfs_log_file_meta) -> String {
+    let mut yaml = String::from("---\nfile:\n  path: ");
+    emit_path(&mut yaml, &relpath_to_path(&file_meta.fm_relpath).to_string_lossy());
+    yaml.push('\n');
+
+    writeln!(yaml, "  size: {}", file_meta.fm_size).unwrap();
+    writeln!(yaml, "  flags: {}", file_meta.fm_flags).unwrap();
+    writeln!(yaml, "  mode: 0{:o}", file_meta.fm_mode).unwrap();
+    writeln!(yaml, "  uid: {}", file_meta.fm_uid).unwrap();
+    writeln!(yaml, "  gid: {}", file_meta.fm_gid).unwrap();
+
+    match file_meta.get_extent() {
+        Extent::Simple { extent } => {
+            let nextents = std::cmp::min(extent.fmap_nextents as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+            writeln!(yaml, "  nextents: {nextents}").unwrap();
+            emit_extents(&mut yaml, "  ", &extent.se[..nextents]);
+        },
+        Extent::Interleaved { extent } => {
+            let niext = std::cmp::min(extent.fmap_niext as usize, FAMFS_MAX_INTERLEAVED_EXTENTS);
+            writeln!(yaml, "  nextents: {niext}").unwrap();
+            writeln!(yaml, "  striped_ext_list:").unwrap();
+            for ie in &extent.se[..niext] {
+                let nstrips = std::cmp::min(ie.ie_nstrips as usize, FAMFS_MAX_SIMPLE_EXTENTS);
+                writeln!(yaml, "  - nstrips: {nstrips}").unwrap();
+                writeln!(yaml, "    chunk_size: {:#x}", ie.ie_chunk_size).unwrap();
+                emit_extents(&mut yaml, "    ", &ie.ie_strips[..nstrips]);
+            }
+        },
+    }
+    yaml.push_str("...\n");
+
+    yaml
+}
+
+// yaml_rust2's own loader resolves scalars itself and reads the C style
+// octal mode (0644) as decimal, so build a tree of the raw scalars
+enum Node {
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>)
+}
+
+#[derive(Default)]
+struct TreeBuilder {
+    stack: Vec<(Node, Option<String>)>, // open collections and a map's pending key
+    docs: Vec<Node>,
+    error: Option<String>
+}
+
+impl TreeBuilder {
+    fn insert(&mut self, node: Node) {
+        let Some((parent, key)) = self.stack.last_mut() else {
+            self.docs.push(node);
+            return;
+        };
+
+        match (parent, node) {
+            (Node::Seq(items), node) => items.push(node),
+            (Node::Map(_), Node::Scalar(k)) if key.is_none() => *key = Some(k),
+            (Node::Map(_), _) if key.is_none() => self.error = Some("non scalar key".into()),
+            (Node::Map(entries), node) => entries.push((key.take().unwrap(), node)),
+            (Node::Scalar(_), _) => unreachable!("scalars are never open"),
+        }
+    }
+}
+
+impl EventReceiver for TreeBuilder {
+    fn on_event(&mut self, ev: Event) {
+        match ev {
+            Event::Scalar(value, ..) => self.insert(Node::Scalar(value)),
+            Event::SequenceStart(..) => self.stack.push((Node::Seq(Vec::new()), None)),
+            Event::MappingStart(..) => self.stack.push((Node::Map(Vec::new()), None)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.insert(node);
+                }
+            },
+            Event::Alias(_) => self.error = Some("aliases aren't supported".into()),
+            _ => {},
+        }
+    }
+}
+
+fn get<'a>(map: &'a [(String, Node)], key: &'static str) -> Result<&'a Node, ShadowError> {
+    map.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, node)| node)
+        .ok_or(ShadowError::Missing(key))
+}
+
+fn check_keys(map: &[(String, Node)], known: &[&str]) -> Result<(), ShadowError> {
+    match map.iter().find(|(k, _)| !known.contains(&k.as_str())) {
+        Some((k, _)) => Err(ShadowError::UnknownKey(k.clone())),
+        None => Ok(()),
+    }
+}
+
+fn scalar<'a>(map: &'a [(String, Node)], key: &'static str) -> Result<&'a str, ShadowError> {
+    match get(map, key)? {
+        Node::Scalar(value) => Ok(value),
+        _ => Err(ShadowError::BadValue(key, "not a scalar".into())),
+    }
+}
+
+fn seq<'a>(map: &'a [(String, Node)], key: &'static str) -> Result<&'a [Node], ShadowError> {
+    match get(map, key)? {
+        Node::Seq(items) => Ok(items),
+        _ => Err(ShadowError::BadValue(key, "not a list".into())),
+    }
+}
+
+fn as_map<'a>(node: &'a Node, key: &'static str) -> Result<&'a [(String, Node)], ShadowError> {
+    match node {
+        Node::Map(entries) => Ok(entries),
+        _ => Err(ShadowError::BadValue(key, "not a mapping".into())),
+    }
+}
+
+// Numbers are read like strtoull with base 0: 0x is hex, a leading 0 octal
+fn num(map: &[(String, Node)], key: &'static str) -> Result<u64, ShadowError> {
+    let value = scalar(map, key)?;
+    let parsed = if let Some(hex) = value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if value.len() > 1 && value.starts_with('0') {
+        u64::from_str_radix(&value[1..], 8)
+    } else {
+        value.parse()
+    };
+
+    parsed.map_err(|_| ShadowError::BadValue(key, value.to_string()))
+}
+
+fn num32(map: &[(String, Node)], key: &'static str) -> Result<u32, ShadowError> {
+    let value = num(map, key)?;
+    u32::try_from(value).map_err(|_| ShadowError::BadValue(key, value.to_string()))
+}
+
+fn parse_extents(map: &[(String, Node)]) -> Result<Vec<famfs_simple_extent>, ShadowError> {
+    let list = seq(map, "simple_ext_list")?;
+    if list.len() > FAMFS_MAX_SIMPLE_EXTENTS {
+        return Err(ShadowError::TooManyExtents);
+    }
+
+    list.iter()
+        .map(|node| {
+            let se = as_map(node, "simple_ext_list")?;
+            check_keys(se, &["offset", "length", "devindex"])?;
+
+            Ok(famfs_simple_extent {
+                se_devindex: if get(se, "devindex").is_ok() { num(se, "devindex")? } else { 0 },
+                se_offset: num(se, "offset")?,
+                se_len: num(se, "length")?,
+            })
+        })
+        .collect()
+}
+
+/// Reads a shadow yaml document back into the log metadata it describes.
+/// The document has to be complete and self consistent (the extent
+/// counts have to match the lists), unknown keys are rejected.
+pub fn parse_file_yaml(yaml: &str) -> Result<famfs_log_file_meta, ShadowError> {
+    let mut builder = TreeBuilder::default();
+    Parser::new_from_str(yaml)
+        .load(&mut builder, false)
+        .map_err(|err| ShadowError::Yaml(err.to_string()))?;
+    if let Some(err) = builder.error {
+        return Err(ShadowError::Yaml(err));
+    }
+
+    let doc = builder.docs.first().ok_or(ShadowError::Missing("file"))?;
+    let file = as_map(get(as_map(doc, "file")?, "file")?, "file")?;
+    check_keys(file, &[
+        "path", "size", "flags", "mode", "uid", "gid", "nextents", "simple_ext_list", "striped_ext_list"
+    ])?;
+
+    let path = scalar(file, "path")?;
+    let relpath = RelPath::new(Path::new(path)).map_err(ShadowError::Path)?;
+
+    let nextents = num(file, "nextents")?;
+    let fmap = match (get(file, "simple_ext_list"), get(file, "striped_ext_list")) {
+        (Ok(_), Err(_)) => {
+            let extents = parse_extents(file)?;
+            if nextents != extents.len() as u64 {
+                return Err(ShadowError::BadValue("nextents", nextents.to_string()));
+            }
+            famfs_log_fmap::simple(&extents).ok_or(ShadowError::TooManyExtents)?
+        },
+        (Err(_), Ok(_)) => {
+            let list = seq(file, "striped_ext_list")?;
+            if nextents != list.len() as u64 {
+                return Err(ShadowError::BadValue("nextents", nextents.to_string()));
+            }
+            if list.len() > FAMFS_MAX_INTERLEAVED_EXTENTS {
+                return Err(ShadowError::TooManyExtents);
+            }
+
+            let interleaved = list.iter()
+                .map(|node| {
+                    let ie = as_map(node, "striped_ext_list")?;
+                    check_keys(ie, &["nstrips", "chunk_size", "simple_ext_list"])?;
+
+                    let strips = parse_extents(ie)?;
+                    let nstrips = num(ie, "nstrips")?;
+                    if nstrips != strips.len() as u64 {
+                        return Err(ShadowError::BadValue("nstrips", nstrips.to_string()));
+                    }
+
+                    let mut ie_strips = [famfs_simple_extent::default(); FAMFS_MAX_SIMPLE_EXTENTS];
+                    ie_strips[..strips.len()].copy_from_slice(&strips);
+                    Ok(famfs_interleaved_ext {
+                        ie_nstrips: nstrips,
+                        ie_chunk_size: num(ie, "chunk_size")?,
+                        ie_strips,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            famfs_log_fmap::interleaved(&interleaved).ok_or(ShadowError::TooManyExtents)?
+        },
+        (Ok(_), Ok(_)) => return Err(ShadowError::UnknownKey("striped_ext_list".into())),
+        (Err(_), Err(_)) => return Err(ShadowError::Missing("simple_ext_list")),
+    };
+
+    Ok(famfs_log_file_meta::new(
+        relpath.to_relpath(),
+        num(file, "size")?,
+        num32(file, "flags")?,
+        num32(file, "mode")?,
+        num32(file, "uid")?,
+        num32(file, "gid")?,
+        fmap,
+    ))
+}
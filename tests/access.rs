@@ -0,0 +1,96 @@
+// Access checks: opening a file as some credentials needs the permission
+// bits of the file and search permission on every directory on the way.
+
+mod common;
+
+use std::path::Path;
+
+use common::new_famfs;
+use famfs_rs::access::{Credentials, ACCESS_EXEC, ACCESS_READ};
+use famfs_rs::meta::{FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+
+#[test]
+fn permission_checks() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("dir0"), 0o750, 1000, 1000).unwrap();
+        log.make_file(Path::new("dir0/file0"), 0o640, 1000, 1000, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("file1"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    }
+
+    let owner = Credentials::new(1000, 1000, vec![]);
+    let member = Credentials::new(2000, 3000, vec![1000]);
+    let other = Credentials::new(2000, 2000, vec![]);
+    let root = Credentials::new(0, 0, vec![]);
+    let file0 = Path::new("dir0/file0");
+    let eacces = -(libc::EACCES as i64);
+
+    assert!(fs.open_file(file0, &owner, true).is_ok());
+    assert!(fs.open_file(file0, &member, false).is_ok());
+    assert_eq!(fs.open_file(file0, &member, true).err(), Some(eacces));
+    // others are stopped at the directory
+    assert_eq!(fs.access(file0, &other, ACCESS_READ), Err(eacces));
+    assert_eq!(fs.access(Path::new("dir0"), &other, ACCESS_EXEC), Err(eacces));
+
+    assert!(fs.open_file(file0, &root, true).is_ok());
+    assert_eq!(fs.access(file0, &root, ACCESS_EXEC), Err(eacces));
+    assert_eq!(fs.open_file(Path::new("file1"), &root, true).err(), Some(-(libc::EROFS as i64)));
+    assert!(fs.open_file(Path::new("file1"), &other, false).is_ok());
+
+    assert_eq!(fs.open_file(Path::new("dir0/nope"), &owner, false).err(), Some(-(libc::ENOENT as i64)));
+    assert_eq!(fs.open_file(Path::new("file1/x"), &owner, false).err(), Some(-(libc::ENOTDIR as i64)));
+    assert_eq!(fs.open_file(Path::new("dir0"), &owner, false).err(), Some(-(libc::EISDIR as i64)));
+}
+
+#[test]
+fn links_are_searched_where_they_live() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("private"), 0o700, 0, 0).unwrap();
+        log.make_dir(Path::new("public"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("public/data"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("private/secret"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        // a link in a directory we can't search, to a file we could read
+        log.make_symlink(Path::new("private/out"), Path::new("../public/data"), 0, 0).unwrap();
+        // a link we can reach, through a directory we can't search
+        log.make_symlink(Path::new("public/in"), Path::new("/private/secret"), 0, 0).unwrap();
+        log.make_symlink(Path::new("public/dir"), Path::new("../private"), 0, 0).unwrap();
+    }
+
+    let user = Credentials::new(1000, 1000, vec![]);
+    let root = Credentials::new(0, 0, vec![]);
+    let eacces = Some(-(libc::EACCES as i64));
+
+    assert!(fs.open_file(Path::new("public/data"), &user, false).is_ok());
+    for path in ["private/out", "public/in", "public/dir/secret", "public/dir/out"] {
+        assert_eq!(fs.open_file(Path::new(path), &user, false).err(), eacces, "{path}");
+        assert!(fs.open_file(Path::new(path), &root, false).is_ok(), "{path}");
+    }
+}
+
+#[test]
+fn access_follows_links_like_open() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("private"), 0o700, 0, 0).unwrap();
+        log.make_dir(Path::new("public"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("public/data"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("private/secret"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_symlink(Path::new("pub"), Path::new("public"), 0, 0).unwrap();
+        log.make_symlink(Path::new("priv"), Path::new("private"), 0, 0).unwrap();
+        log.make_symlink(Path::new("loop"), Path::new("loop"), 0, 0).unwrap();
+    }
+
+    let user = Credentials::new(1000, 1000, vec![]);
+    for path in ["pub/data", "public/data", "priv/secret", "loop/x", "pub/none"] {
+        let open = fs.open_file(Path::new(path), &user, false).map(|_| ());
+        assert_eq!(fs.access(Path::new(path), &user, ACCESS_READ), open, "{path}");
+    }
+    assert_eq!(fs.access(Path::new("pub"), &user, ACCESS_READ | ACCESS_EXEC), Ok(()));
+    assert_eq!(fs.access(Path::new("pub/data"), &user, ACCESS_READ), Ok(()));
+    assert_eq!(fs.access(Path::new("priv/secret"), &user, ACCESS_READ), Err(-(libc::EACCES as i64)));
+    assert_eq!(fs.access(Path::new("loop/x"), &user, ACCESS_READ), Err(-(libc::ELOOP as i64)));
+}
@@ -0,0 +1,29 @@
+// The allocation bitmap as the library and the bitmap command print it.
+
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use common::{new_famfs, MB2};
+use famfs_rs::meta::{FAMFS_FM_ALL_HOSTS_RW, MIN_DEVSIZE};
+
+#[test]
+fn bitmap_shows_the_units_in_use() {
+    let (image, mut fs) = new_famfs();
+    let bitmap = {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("file0"), 0o644, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+
+        let bitmap = log.bitmap_string();
+        assert_eq!(bitmap.len() as u64, MIN_DEVSIZE as u64 / MB2);
+        assert!(bitmap.chars().all(|c| c == '0' || c == '1'));
+        assert_eq!(bitmap.matches('1').count() as u64 * MB2, log.allocated_bytes());
+        bitmap
+    };
+    drop(fs);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_famfs-rs")).arg("bitmap").arg(image.path()).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{bitmap}\n"));
+}
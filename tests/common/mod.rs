@@ -0,0 +1,67 @@
+// Helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::fs::File;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use famfs_rs::{DirtyPages, Famfs, FamfsMetadataInterface, FlushStats, MMAPed};
+use famfs_rs::cache::Persistence;
+use famfs_rs::meta::{famfs_log, famfs_superblock, MIN_DEVSIZE};
+use tempfile::NamedTempFile;
+
+pub const MB2: u64 = 0x200000;
+
+// A fresh famfs on an image file of the smallest size mkfs takes, the
+// image goes away with the returned file
+pub fn new_famfs() -> (NamedTempFile, Famfs) {
+    let image = NamedTempFile::new().unwrap();
+    image.as_file().set_len(MIN_DEVSIZE as u64).unwrap();
+    let fs = Famfs::mkfs(image.path(), false).unwrap();
+
+    (image, fs)
+}
+
+// An image whose commits fail with EIO while `fail` is set
+pub struct FailingCommits {
+    pub mmaped: MMAPed,
+    pub fail: Rc<Cell<bool>>
+}
+
+impl FamfsMetadataInterface for FailingCommits {
+    fn superblock(&self) -> NonNull<famfs_superblock> {
+        self.mmaped.superblock()
+    }
+
+    fn log(&self) -> NonNull<famfs_log> {
+        self.mmaped.log()
+    }
+
+    fn mapped_len(&self) -> usize {
+        self.mmaped.mapped_len()
+    }
+
+    fn mark_dirty(&mut self, range: DirtyPages) {
+        self.mmaped.mark_dirty(range);
+    }
+
+    fn lock_file(&self) -> std::io::Result<File> {
+        self.mmaped.lock_file()
+    }
+
+    fn commit(&mut self) -> std::io::Result<()> {
+        if self.fail.get() {
+            return Err(std::io::Error::from_raw_os_error(libc::EIO));
+        }
+        self.mmaped.commit()
+    }
+
+    fn flush_stats(&self) -> FlushStats {
+        self.mmaped.flush_stats()
+    }
+
+    fn persistence(&self) -> Persistence {
+        self.mmaped.persistence()
+    }
+}
@@ -0,0 +1,47 @@
+// Creating files and directories: paths have to be free and sit in an
+// existing directory, and nothing that fails reaches the log.
+
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+
+use common::{new_famfs, MB2};
+use famfs_rs::meta::{FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+
+#[test]
+fn create_needs_a_free_path_in_a_directory() {
+    let (_image, mut fs) = new_famfs();
+    let mut log = fs.lock_log().unwrap();
+
+    log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    log.make_file(Path::new("dir0/file0"), 0o644, 1000, 1000, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("file1"), 0o600, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    let len = log.namespace().stats().n_entries;
+
+    assert_eq!(log.make_file(Path::new("file1"), 0o600, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW), Err(-(libc::EEXIST as i64)));
+    assert_eq!(log.make_dir(Path::new("dir0/"), 0o755, 0, 0), Err(-(libc::EEXIST as i64)));
+    assert_eq!(log.make_file(Path::new("nodir/file"), 0o600, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW), Err(-(libc::ENOENT as i64)));
+    assert_eq!(log.make_dir(Path::new("file1/dir"), 0o755, 0, 0), Err(-(libc::ENOTDIR as i64)));
+
+    // none of them reached the log
+    assert_eq!(log.namespace().stats().n_entries, len);
+}
+
+#[test]
+fn read_only_files_refuse_writes() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("rw"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("ro"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+        assert_eq!(log.make_file(Path::new("neither"), 0o600, 0, 0, 1, 0), Err(-(libc::EINVAL as i64)));
+    }
+
+    // read only files refuse writes, read only handles refuse them on any file
+    assert_eq!(fs.get_file(Path::new("rw")).unwrap().write(b"data").unwrap(), 4);
+    let err = fs.get_file(Path::new("ro")).unwrap().write(b"data").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    let err = fs.get_file_read_only(Path::new("rw")).unwrap().write(b"data").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
@@ -0,0 +1,31 @@
+// Deleting files, which gives their space back to the allocator.
+
+mod common;
+
+use std::path::Path;
+
+use common::{new_famfs, MB2};
+use famfs_rs::meta::FAMFS_FM_ALL_HOSTS_RW;
+
+#[test]
+fn deletes_release_the_space() {
+    let (_image, mut fs) = new_famfs();
+    let mut log = fs.lock_log().unwrap();
+    let empty = log.allocated_bytes();
+
+    log.make_file(Path::new("file0"), 0o644, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("file1"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    assert_eq!(log.allocated_bytes(), empty + 4 * MB2);
+
+    log.delete_file(Path::new("file0")).unwrap();
+    assert_eq!(log.allocated_bytes(), empty + MB2);
+    log.make_file(Path::new("file2"), 0o644, 0, 0, 2 * MB2, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    assert_eq!(log.allocated_bytes(), empty + 3 * MB2);
+    assert_eq!(log.delete_file(Path::new("file0")), Err(-(libc::ENOENT as i64)));
+    drop(log);
+
+    // a bitmap rebuilt from the log agrees
+    let report = fs.fsck();
+    assert!(report.is_clean(), "{report}");
+    assert_eq!(report.allocated_bytes, empty + 3 * MB2);
+}
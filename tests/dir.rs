@@ -0,0 +1,42 @@
+// read_dir and walk over the namespace.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{new_famfs, MB2};
+use famfs_rs::meta::{FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+use famfs_rs::namespace::NodeKind;
+
+#[test]
+fn read_dir_and_walk() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("dir0/file0"), 0o644, 1000, 1000, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_dir(Path::new("dir0/sub"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("dir0/sub/file2"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("file1"), 0o600, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    }
+
+    let names: Vec<_> = fs.read_dir(Path::new("")).unwrap().iter().map(|entry| entry.name().to_owned()).collect();
+    assert_eq!(names, ["dir0", "file1"]);
+    let names: Vec<_> = fs.read_dir(Path::new("dir0/")).unwrap().iter().map(|entry| entry.name().to_owned()).collect();
+    assert_eq!(names, ["file0", "sub"]);
+    assert_eq!(fs.read_dir(Path::new("file1")).unwrap_err(), -(libc::ENOTDIR as i64));
+    assert_eq!(fs.read_dir(Path::new("nodir")).unwrap_err(), -(libc::ENOENT as i64));
+
+    // directories come ahead of their contents
+    let walked: Vec<PathBuf> = fs.walk(Path::new("")).unwrap().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(walked, ["dir0", "dir0/file0", "dir0/sub", "dir0/sub/file2", "file1"].map(PathBuf::from));
+    let walked: Vec<PathBuf> = fs.walk(Path::new("dir0")).unwrap().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(walked, ["dir0/file0", "dir0/sub", "dir0/sub/file2"].map(PathBuf::from));
+
+    let big: Vec<PathBuf> = fs.walk(Path::new("")).unwrap().min_len(MB2).map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(big, [PathBuf::from("file1")]);
+    let small = fs.walk(Path::new("")).unwrap().files().max_len(4096).count();
+    assert_eq!(small, 2);
+    let dirs: Vec<NodeKind> = fs.walk(Path::new("")).unwrap().dirs().map(|entry| entry.metadata().kind()).collect();
+    assert_eq!(dirs, [NodeKind::Dir, NodeKind::Dir]);
+}
@@ -0,0 +1,204 @@
+// File handles: slices and segments of the mapping, reads and writes
+// through them and how handles on the same file exclude each other.
+
+mod common;
+
+use std::io::{IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use common::{new_famfs, MB2};
+use famfs_rs::{Famfs, FamfsMetadataInterface, MMAPed, WriteMode};
+use famfs_rs::cache::{Coherence, Persistence};
+use famfs_rs::meta::{famfs_log_fmap, famfs_simple_extent, FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+use famfs_rs::relpath::RelPath;
+
+#[test]
+fn slices_borrow_the_mapping() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("file1"), 0o600, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    }
+
+    // any number of files can be open at once
+    let mut file0 = fs.get_file(Path::new("file0")).unwrap();
+    let mut file1 = fs.get_file(Path::new("file1")).unwrap();
+    file0.as_mut_slice().unwrap()[..4].copy_from_slice(b"DATA");
+    assert_eq!(&file0.as_slice().unwrap()[..4], b"DATA");
+    assert_eq!(file0.as_slice().unwrap().len(), 4096);
+
+    // read only files have no mutable slice
+    assert!(file1.as_mut_slice().is_none());
+    assert_eq!(file1.segments().map(<[u8]>::len).collect::<Vec<_>>(), [3 * MB2 as usize]);
+}
+
+#[test]
+fn writable_handles_exclude_others() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("file1"), 0o600, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    }
+
+    // a writable handle keeps every other one off its file, read only
+    // files can be opened any number of times
+    let mut file0 = fs.get_file(Path::new("file0")).unwrap();
+    file0.write_all(b"DATA").unwrap();
+    assert!(fs.get_file_read_only(Path::new("file0")).is_none());
+    let file1 = fs.get_file(Path::new("file1")).unwrap();
+    assert!(fs.get_file_read_only(Path::new("file1")).is_some());
+    drop(file0);
+
+    let mut file0 = fs.get_file_read_only(Path::new("file0")).unwrap();
+    assert!(fs.get_file(Path::new("file0")).is_none());
+    assert!(fs.get_file_read_only(Path::new("file0")).is_some());
+    let mut data = [0; 4];
+    file0.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"DATA");
+
+    // reads and writes stop at the end of the file
+    assert_eq!(file0.seek(SeekFrom::End(-2)).unwrap(), 4094);
+    assert_eq!(file0.read(&mut data).unwrap(), 2);
+    assert_eq!(file0.read(&mut data).unwrap(), 0);
+    drop(file0);
+    let mut file0 = fs.get_file(Path::new("file0")).unwrap();
+    file0.seek(SeekFrom::Start(4095)).unwrap();
+    assert_eq!(file0.write_all(b"xy").unwrap_err().kind(), std::io::ErrorKind::WriteZero);
+    drop((file0, file1));
+}
+
+// Logs a read write file made of (offset, len) extents straight through
+// the mapping, the allocator only hands out contiguous files
+fn log_extents(image: &Path, path: &str, size: u64, extents: &[(u64, u64)]) {
+    let mut mmaped = MMAPed::open(image).unwrap();
+    let extents: Vec<famfs_simple_extent> = extents
+        .iter()
+        .map(|&(offset, len)| famfs_simple_extent { se_devindex: 0, se_offset: offset, se_len: len })
+        .collect();
+    let fmap = famfs_log_fmap::simple(&extents).unwrap();
+    let path = RelPath::new(Path::new(path)).unwrap();
+
+    let log = unsafe { mmaped.log().as_mut() };
+    unsafe { log.log_file_create(&fmap, &path, 0o644, 0, 0, size, FAMFS_FM_ALL_HOSTS_RW, &mut mmaped) }.unwrap();
+    mmaped.commit().unwrap();
+}
+
+#[test]
+fn vectored_io() {
+    let (image, fs) = new_famfs();
+    drop(fs);
+
+    log_extents(image.path(), "src", 5 * MB2 / 2, &[(16 * MB2, MB2), (40 * MB2, MB2), (20 * MB2, MB2)]);
+    log_extents(image.path(), "dst", 3 * MB2, &[(60 * MB2, 2 * MB2), (50 * MB2, MB2)]);
+    let mut fs = Famfs::open(image.path()).unwrap();
+
+    let pattern: Vec<u8> = (0..5 * MB2 / 2).map(|i| (i % 251) as u8).collect();
+    let mut src = fs.get_file(Path::new("src")).unwrap();
+    assert_eq!(src.segments().count(), 3);
+    assert!(src.as_slice().is_none());
+
+    // the buffers split the file at different places than its extents
+    let (a, rest) = pattern.split_at(MB2 as usize / 3);
+    let (b, c) = rest.split_at(MB2 as usize);
+    assert_eq!(src.write_vectored(&[IoSlice::new(a), IoSlice::new(&[]), IoSlice::new(b), IoSlice::new(c)]).unwrap(), pattern.len());
+    assert_eq!(src.write(b"x").unwrap(), 0);
+
+    src.rewind().unwrap();
+    let (mut a, mut b) = (vec![0; 3 * MB2 as usize / 2], vec![0; MB2 as usize]);
+    assert_eq!(src.read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)]).unwrap(), pattern.len());
+    assert_eq!([&a[..], &b[..MB2 as usize]].concat(), pattern);
+    assert_eq!(src.segments().flatten().copied().collect::<Vec<_>>(), pattern);
+
+    // copies go mapping to mapping and stop at the end of the shorter file
+    let mut dst = fs.get_file(Path::new("dst")).unwrap();
+    src.seek(SeekFrom::Start(100)).unwrap();
+    dst.seek(SeekFrom::Start(MB2 * 2 - 7)).unwrap();
+    assert_eq!(src.copy_to(&mut dst, MB2 as usize).unwrap(), MB2 as usize);
+    assert_eq!(dst.copy_from(&mut src, usize::MAX).unwrap(), 7);
+
+    let mut copied = vec![0; MB2 as usize + 7];
+    dst.seek(SeekFrom::Start(MB2 * 2 - 7)).unwrap();
+    assert_eq!(dst.read(&mut copied).unwrap(), copied.len());
+    assert_eq!(copied, pattern[100..100 + copied.len()]);
+
+    drop(src);
+    let mut src = fs.get_file_read_only(Path::new("src")).unwrap();
+    assert_eq!(dst.copy_to(&mut src, 1).unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    drop(src);
+
+    // every write mode stores the same bytes, misaligned ends included
+    let mut src = fs.get_file(Path::new("src")).unwrap();
+    for (i, mode) in [WriteMode::NonTemporal, WriteMode::WriteBack, WriteMode::Cached].into_iter().enumerate() {
+        let data: Vec<u8> = pattern.iter().map(|b| b.wrapping_add(i as u8 + 1)).collect();
+        src.set_write_mode(mode);
+        src.seek(SeekFrom::Start(3)).unwrap();
+        src.write_all(&data[3..]).unwrap();
+        src.flush().unwrap();
+
+        src.seek(SeekFrom::Start(3)).unwrap();
+        let mut read = vec![0; data.len() - 3];
+        src.read_exact(&mut read).unwrap();
+        assert_eq!(read, data[3..]);
+    }
+    drop((src, dst));
+
+    // handles and replays take the filesystem's coherence, handles can change theirs
+    assert_eq!(fs.coherence(), Coherence::NonCoherent);
+    fs.set_coherence(Coherence::Coherent);
+    assert_eq!(fs.replay().coherence(), Coherence::Coherent);
+    let mut src = fs.get_file_read_only(Path::new("src")).unwrap();
+    assert_eq!(src.coherence(), Coherence::Coherent);
+    src.set_coherence(Coherence::NonCoherent);
+    let last = pattern.len() - 1;
+    assert_eq!(src.segments().flatten().nth(last), Some(&pattern[last].wrapping_add(3)));
+}
+
+#[test]
+fn flush_syncs_image_files() {
+    let (image, mut fs) = new_famfs();
+    fs.lock_log().unwrap().make_file(Path::new("file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    let offset = fs.stat(Path::new("file0")).unwrap().extents()[0].se_offset;
+
+    // an image isn't DAX memory, writing back cache lines doesn't reach it
+    assert_eq!(MMAPed::open(image.path()).unwrap().persistence(), Persistence::Msync);
+
+    let mut file = fs.get_file(Path::new("file0")).unwrap();
+    for (i, mode) in [WriteMode::Cached, WriteMode::WriteBack, WriteMode::NonTemporal].into_iter().enumerate() {
+        let data = format!("data{i}");
+        file.set_write_mode(mode);
+        file.seek(SeekFrom::Start(3)).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file.flush().unwrap();
+
+        let mut synced = vec![0; data.len()];
+        image.as_file().read_exact_at(&mut synced, offset + 3).unwrap();
+        assert_eq!(synced, data.as_bytes());
+    }
+}
+
+#[test]
+fn handles_on_shared_space_exclude_each_other() {
+    let (image, fs) = new_famfs();
+    drop(fs);
+
+    // a damaged log that gives the second MB2 of a to b as well
+    log_extents(image.path(), "a", 2 * MB2, &[(16 * MB2, 2 * MB2)]);
+    log_extents(image.path(), "b", MB2, &[(17 * MB2, MB2)]);
+    log_extents(image.path(), "c", MB2, &[(18 * MB2, MB2)]);
+    let fs = Famfs::open(image.path()).unwrap();
+
+    let a = fs.get_file(Path::new("a")).unwrap();
+    assert!(fs.get_file(Path::new("b")).is_none());
+    assert!(fs.get_file_read_only(Path::new("b")).is_none());
+    assert!(fs.get_file(Path::new("c")).is_some());
+    drop(a);
+
+    let b = fs.get_file_read_only(Path::new("b")).unwrap();
+    assert!(fs.get_file(Path::new("a")).is_none());
+    assert!(fs.get_file_read_only(Path::new("a")).is_some());
+    drop(b);
+    assert!(fs.get_file(Path::new("a")).is_some());
+}
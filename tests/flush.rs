@@ -0,0 +1,70 @@
+// Persisting metadata: what gets flushed on a commit and what happens when
+// a flush fails.
+
+mod common;
+
+use std::cell::Cell;
+use std::path::Path;
+use std::rc::Rc;
+
+use common::{new_famfs, FailingCommits};
+use famfs_rs::{DirtyPages, FamfsMetadataInterface, MMAPed};
+use famfs_rs::internal::famfs_locked_log;
+use famfs_rs::meta::famfs_log_entry;
+
+#[test]
+fn failed_commits_dont_publish_entries() {
+    let (image, fs) = new_famfs();
+    drop(fs);
+
+    let fail = Rc::new(Cell::new(true));
+    let mut iface = FailingCommits { mmaped: MMAPed::open(image.path()).unwrap(), fail: fail.clone() };
+    let lfd = iface.lock_file().unwrap();
+    let mut log = unsafe { famfs_locked_log::from_log(&mut iface, lfd) }.unwrap();
+
+    assert_eq!(log.make_dir(Path::new("dir0"), 0o755, 0, 0), Err(-(libc::EIO as i64)));
+    assert!(log.stat(Path::new("dir0")).is_none());
+    assert_eq!(log.namespace().next_index(), 0);
+
+    fail.set(false);
+    log.make_dir(Path::new("dir1"), 0o755, 0, 0).unwrap();
+    assert_eq!(log.namespace().next_index(), 1);
+    assert!(log.stat(Path::new("dir1")).is_some());
+}
+
+#[test]
+fn commits_coalesce_dirty_ranges() {
+    let (image, fs) = new_famfs();
+    drop(fs);
+
+    let mut mmaped = MMAPed::open(image.path()).unwrap();
+    // overlapping and adjacent ranges flush as one, log offsets are past
+    // the superblock's so they stay apart
+    mmaped.mark_dirty(DirtyPages::superblock(50, 100));
+    mmaped.mark_dirty(DirtyPages::superblock(0, 100));
+    mmaped.mark_dirty(DirtyPages::superblock(150, 10));
+    mmaped.mark_dirty(DirtyPages::log(8, 8));
+    mmaped.mark_dirty(DirtyPages::log(0, 8));
+    mmaped.mark_dirty(DirtyPages::log(100, 4));
+    mmaped.commit().unwrap();
+
+    let stats = mmaped.flush_stats();
+    assert_eq!((stats.commits, stats.ranges_flushed, stats.bytes_flushed), (1, 3, 160 + 16 + 4));
+
+    mmaped.commit().unwrap();
+    let stats = mmaped.flush_stats();
+    assert_eq!((stats.commits, stats.ranges_flushed, stats.bytes_flushed), (2, 3, 180));
+}
+
+#[test]
+fn appends_flush_the_entry_then_the_header() {
+    let (_image, mut fs) = new_famfs();
+    let before = fs.flush_stats();
+
+    fs.lock_log().unwrap().make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    let after = fs.flush_stats();
+    // the header's seqnum and index are next to each other and go as one
+    assert_eq!(after.commits - before.commits, 2);
+    assert_eq!(after.ranges_flushed - before.ranges_flushed, 2);
+    assert_eq!(after.bytes_flushed - before.bytes_flushed, (size_of::<famfs_log_entry>() + 2 * size_of::<u64>()) as u64);
+}
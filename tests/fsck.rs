@@ -0,0 +1,34 @@
+// fsck on damaged images: it has to report them, not crash on them.
+
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use common::{new_famfs, MB2};
+use famfs_rs::MMAPed;
+
+#[test]
+fn truncated_images_are_reported() {
+    let (image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        for i in 0..20 {
+            log.make_dir(Path::new(&format!("dir{i}")), 0o755, 0, 0).unwrap();
+        }
+    }
+    assert!(fs.fsck().is_clean());
+    drop(fs);
+
+    // the log header survives, its entries don't
+    image.as_file().set_len(MB2 + 48).unwrap();
+    let mmaped = MMAPed::open(image.path()).unwrap();
+    let report = mmaped.fsck();
+    assert!(report.superblock_ok && report.log_ok && report.truncated);
+    assert!(!report.is_clean());
+    assert_eq!(report.entries, 0);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_famfs-rs")).arg("fsck").arg(image.path()).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("image:             truncated"));
+}
@@ -0,0 +1,220 @@
+// Checks that this crate reads and writes the same bytes as the C famfs
+// layout. The images are written by tests/golden/gen_golden.py from the C
+// headers rather than by the C tools, see there for how to swap in images
+// the tools made.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use famfs_rs::{shadow, Famfs};
+use famfs_rs::meta::{
+    famfs_log, famfs_log_entry, famfs_superblock, Extent, LogEntry, FAMFS_FM_ALL_HOSTS_RO,
+    FAMFS_FM_ALL_HOSTS_RW, FAMFS_LOG_OFFSET, MIN_DEVSIZE,
+};
+use famfs_rs::namespace::{Namespace, NodeKind};
+
+const MB2: u64 = 0x200000;
+const DATA: u64 = 0xa00000;
+
+struct Image {
+    sb: Vec<u8>,
+    log: Vec<u8>,
+}
+
+fn golden(name: &str) -> Image {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+
+    Image {
+        sb: std::fs::read(dir.join(format!("{name}.sb"))).unwrap(),
+        log: std::fs::read(dir.join(format!("{name}.log"))).unwrap(),
+    }
+}
+
+// Runs every check an image has to pass and returns its entries
+fn check_image(image: &Image) -> Vec<famfs_log_entry> {
+    let sb = famfs_superblock::decode(&image.sb).unwrap();
+    assert!(sb.check_superblock());
+    assert_eq!(sb.log_offset(), FAMFS_LOG_OFFSET);
+    assert_eq!(sb.encode()[..], image.sb[..size_of::<famfs_superblock>()]);
+
+    let log = famfs_log::decode(&image.log).unwrap();
+    assert!(log.check_log());
+    assert!(log.check_crc());
+    assert_eq!(log.encode()[..], image.log[..size_of::<famfs_log>()]);
+    assert_eq!(log.byte_len(), sb.log_len());
+    assert!(log.len() <= log.max_size());
+
+    let entries: Vec<famfs_log_entry> = (0..log.len() as usize)
+        .map(|i| {
+            let raw = &image.log[famfs_log::entry_offset(i)..famfs_log::entry_offset(i + 1)];
+            let entry = famfs_log_entry::decode(raw).unwrap();
+
+            assert!(entry.check_crc(), "entry {i} fails its crc");
+            assert_eq!(entry.seqnum(), i as u64);
+            assert_eq!(entry.encode()[..], raw[..]);
+            entry
+        })
+        .collect();
+
+    // replaying the log in place has to agree with decoding it
+    let mut mapped = vec![0u64; image.log.len().div_ceil(size_of::<u64>())];
+    let mapped_bytes = unsafe { std::slice::from_raw_parts_mut(mapped.as_mut_ptr().cast::<u8>(), image.log.len()) };
+    mapped_bytes.copy_from_slice(&image.log);
+
+    let ns = unsafe { Namespace::replay(mapped.as_ptr().cast::<famfs_log>()) };
+    assert_eq!(ns.stats().n_entries, entries.len() as u64);
+    assert_eq!(ns.stats().bad_entries, 0);
+    assert_eq!(ns.next_index(), log.len());
+
+    entries
+}
+
+fn simple_extents(entry: &famfs_log_entry) -> Vec<(u64, u64)> {
+    let LogEntry::File { file_meta } = entry.get_entry_type() else { panic!("not a file") };
+    let Extent::Simple { extent } = file_meta.get_extent() else { panic!("not a simple extent") };
+
+    extent.se[..extent.fmap_nextents as usize]
+        .iter()
+        .map(|se| (se.se_offset, se.se_len))
+        .collect()
+}
+
+fn relpath(entry: &famfs_log_entry) -> PathBuf {
+    match entry.get_entry_type() {
+        LogEntry::File { file_meta } => file_meta.relpath().to_path_buf(),
+        LogEntry::MakeDir { dir_meta } => dir_meta.relpath().to_path_buf(),
+        _ => panic!("entry has no path"),
+    }
+}
+
+#[test]
+fn golden_simple() {
+    let entries = check_image(&golden("simple"));
+    assert_eq!(entries.len(), 4);
+
+    let paths: Vec<PathBuf> = entries.iter().map(relpath).collect();
+    assert_eq!(paths, ["file0", "dir0", "dir0/file1", "dir0/dir1"].map(PathBuf::from));
+
+    let LogEntry::File { file_meta } = entries[0].get_entry_type() else { panic!() };
+    assert_eq!(file_meta.fm_size, 4096);
+    assert_eq!(file_meta.fm_mode, 0o644);
+    assert_eq!(file_meta.fm_flags, FAMFS_FM_ALL_HOSTS_RW);
+    assert_eq!(simple_extents(&entries[0]), [(DATA, MB2)]);
+
+    let LogEntry::File { file_meta } = entries[2].get_entry_type() else { panic!() };
+    assert_eq!(file_meta.fm_size, 3 * MB2);
+    assert_eq!((file_meta.fm_uid, file_meta.fm_gid, file_meta.fm_mode), (1000, 1000, 0o600));
+    assert_eq!(simple_extents(&entries[2]), [(DATA + MB2, 3 * MB2)]);
+
+    assert!(matches!(entries[1].get_entry_type(), LogEntry::MakeDir { .. }));
+    assert!(matches!(entries[3].get_entry_type(), LogEntry::MakeDir { .. }));
+}
+
+#[test]
+fn golden_multi_extent() {
+    let entries = check_image(&golden("multi_extent"));
+    assert_eq!(entries.len(), 2);
+
+    let LogEntry::File { file_meta } = entries[1].get_entry_type() else { panic!() };
+    assert_eq!(file_meta.fm_size, 5 * MB2);
+    assert_eq!(file_meta.fm_flags, FAMFS_FM_ALL_HOSTS_RO);
+    assert_eq!(
+        simple_extents(&entries[1]),
+        [(DATA, MB2), (DATA + 4 * MB2, 2 * MB2), (DATA + 8 * MB2, 2 * MB2)]
+    );
+}
+
+#[test]
+fn golden_interleaved() {
+    let entries = check_image(&golden("interleaved"));
+    assert_eq!(entries.len(), 3);
+    assert_eq!(relpath(&entries[1]), Path::new("stripes/striped"));
+
+    let LogEntry::File { file_meta } = entries[1].get_entry_type() else { panic!() };
+    let Extent::Interleaved { extent } = file_meta.get_extent() else { panic!("not interleaved") };
+    assert_eq!(extent.fmap_niext, 1);
+
+    let ie = &extent.se[0];
+    assert_eq!(ie.ie_nstrips, 2);
+    assert_eq!(ie.ie_chunk_size, MB2);
+    assert_eq!((ie.ie_strips[0].se_offset, ie.ie_strips[0].se_len), (DATA, 2 * MB2));
+    assert_eq!((ie.ie_strips[1].se_offset, ie.ie_strips[1].se_len), (DATA + 16 * MB2, 2 * MB2));
+
+    // chunks alternate between the strips, the file's size cuts the last one
+    let segments: Vec<(u64, u64)> = file_meta.segments().iter().map(|se| (se.se_offset, se.se_len)).collect();
+    assert_eq!(segments, [(DATA, MB2), (DATA + 16 * MB2, MB2), (DATA + MB2, MB2), (DATA + 17 * MB2, MB2)]);
+
+    assert_eq!(simple_extents(&entries[2]), [(DATA + 2 * MB2, MB2)]);
+}
+
+#[test]
+fn writer_round_trip() {
+    let image = tempfile::NamedTempFile::new().unwrap();
+    image.as_file().set_len(MIN_DEVSIZE as u64).unwrap();
+
+    {
+        let mut fs = Famfs::mkfs(image.path(), false).unwrap();
+        let mut log = fs.lock_log().unwrap();
+
+        log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("dir0/file0"), 0o644, 1000, 1000, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("file1"), 0o600, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    }
+
+    // a valid famfs isn't overwritten without force
+    assert!(Famfs::mkfs(image.path(), false).is_err());
+    assert!(Famfs::open(image.path()).is_ok());
+
+    let mut file = File::open(image.path()).unwrap();
+    let mut sb = vec![0; size_of::<famfs_superblock>()];
+    file.read_exact(&mut sb).unwrap();
+
+    let mut log = vec![0; famfs_log::entry_offset(3)];
+    file.seek(SeekFrom::Start(FAMFS_LOG_OFFSET)).unwrap();
+    file.read_exact(&mut log).unwrap();
+
+    let entries = check_image(&Image { sb, log });
+    let paths: Vec<PathBuf> = entries.iter().map(relpath).collect();
+    assert_eq!(paths, ["dir0", "dir0/file0", "file1"].map(PathBuf::from));
+
+    let LogEntry::File { file_meta } = entries[1].get_entry_type() else { panic!() };
+    assert_eq!((file_meta.fm_size, file_meta.fm_uid, file_meta.fm_gid, file_meta.fm_mode), (4096, 1000, 1000, 0o644));
+    assert_eq!(simple_extents(&entries[1]).len(), 1);
+
+    let ns = {
+        let mut mapped = vec![0u64; 1 << 16];
+        let bytes = unsafe { std::slice::from_raw_parts_mut(mapped.as_mut_ptr().cast::<u8>(), famfs_log::entry_offset(3)) };
+        file.seek(SeekFrom::Start(FAMFS_LOG_OFFSET)).unwrap();
+        file.read_exact(bytes).unwrap();
+        unsafe { Namespace::replay(mapped.as_ptr().cast::<famfs_log>()) }
+    };
+    assert_eq!(ns.lookup(Path::new("dir0")).unwrap().kind, NodeKind::Dir);
+    assert_eq!(ns.lookup(Path::new("file1")).unwrap().kind, NodeKind::File);
+}
+
+#[test]
+fn shadow_yaml_round_trip() {
+    for name in ["simple", "multi_extent", "interleaved"] {
+        for entry in check_image(&golden(name)) {
+            let LogEntry::File { file_meta } = entry.get_entry_type() else { continue };
+
+            let yaml = shadow::emit_file_yaml(file_meta);
+            let parsed = shadow::parse_file_yaml(&yaml).unwrap_or_else(|err| panic!("{name}: {err}\n{yaml}"));
+            assert!(parsed == *file_meta, "{name}: yaml doesn't read back the same\n{yaml}");
+        }
+    }
+
+    // the C tools write modes in octal and numbers in any base strtoull takes
+    let meta = shadow::parse_file_yaml(
+        "---\nfile:\n  path: a/b\n  size: 4096\n  flags: 0x2\n  mode: 0644\n  uid: 10\n  gid: 010\n  \
+         nextents: 1\n  simple_ext_list:\n  - offset: 0xa00000\n    length: 2097152\n...\n"
+    ).unwrap();
+    assert_eq!((meta.fm_mode, meta.fm_uid, meta.fm_gid, meta.fm_flags), (0o644, 10, 8, FAMFS_FM_ALL_HOSTS_RW));
+    assert_eq!(meta.extent_list()[0].se_len, MB2);
+
+    assert!(matches!(
+        shadow::parse_file_yaml("file:\n  path: a\n  size: 1\n  flags: 0\n  mode: 0\n  uid: 0\n  gid: 0\n  nextents: 2\n  simple_ext_list: []\n"),
+        Err(shadow::ShadowError::BadValue("nextents", _))
+    ));
+}
@@ -0,0 +1,164 @@
+#!/usr/bin/env python3
+# Writes the golden famfs images used by tests/golden.rs.
+#
+# The structures are laid out by hand from the upstream C famfs headers
+# (famfs_meta.h), independently of this crate, so the tests catch this
+# crate drifting from the layout as transcribed here. They can't catch a
+# mistake in the transcription itself: the checked in images come from
+# this script, not from the upstream tools, and should be replaced by
+# images the C mkfs.famfs and famfs creat/mkdir made.
+#
+# Each image is stored as two files to keep them small: <name>.sb holds
+# the superblock and <name>.log holds the log header followed by the
+# entries that are in use. Images made by the upstream tools are cut
+# down the same way:
+#   dd if=<image> of=<name>.sb bs=200 count=1
+#   dd if=<image> of=<name>.log bs=1 skip=$((0x200000)) count=$((48 + 544 * N))
+# where N is famfs_log_next_index.
+
+import os
+import struct
+import uuid
+import zlib
+
+FAMFS_SUPER_MAGIC = 0x87b282ff
+FAMFS_CURRENT_VERSION = 47
+FAMFS_LOG_OFFSET = 0x200000
+FAMFS_LOG_LEN = 0x800000
+FAMFS_ALLOC_UNIT = 0x200000
+FAMFS_OMF_VER_MAJOR = 2
+FAMFS_OMF_VER_MINOR = 1
+FAMFS_LOG_MAGIC = 0xbadcafef00d
+FAMFS_MAX_PATHLEN = 80
+FAMFS_MAX_SIMPLE_EXTENTS = 16
+FAMFS_FM_ALL_HOSTS_RO = 1 << 0
+FAMFS_FM_ALL_HOSTS_RW = 1 << 1
+
+FAMFS_LOG_FILE = 0
+FAMFS_LOG_MKDIR = 1
+
+FAMFS_EXT_SIMPLE = 0
+FAMFS_EXT_INTERLEAVE = 1
+
+SB_SIZE = 200
+LOG_HDR_SIZE = 48
+ENTRY_SIZE = 544
+ENTRY_UNION_SIZE = 520
+FMAP_INNER_SIZE = 408
+
+DEVSIZE = 8 << 30
+
+
+def superblock(name):
+    sb = struct.pack(
+        "<QQQQQII16s16s16s",
+        FAMFS_SUPER_MAGIC,
+        FAMFS_CURRENT_VERSION,
+        FAMFS_LOG_OFFSET,
+        FAMFS_LOG_LEN,
+        FAMFS_ALLOC_UNIT,
+        FAMFS_OMF_VER_MAJOR,
+        FAMFS_OMF_VER_MINOR,
+        uuid.uuid5(uuid.NAMESPACE_URL, "famfs-golden-" + name).bytes,
+        uuid.uuid5(uuid.NAMESPACE_URL, "famfs-golden-dev-" + name).bytes,
+        uuid.UUID(int=0).bytes,
+    )
+    crc = zlib.crc32(sb)
+    # ts_crc, ts_pad, ts_sb_flags, padding, then struct famfs_daxdev
+    sb += struct.pack("<IIIxxxx", crc, 0, 1)
+    sb += struct.pack("<Q16s64s", DEVSIZE, uuid.UUID(int=0).bytes, b"")
+    assert len(sb) == SB_SIZE
+    return sb
+
+
+def log_header(nentries):
+    last_index = (FAMFS_LOG_LEN - LOG_HDR_SIZE) // ENTRY_SIZE - 1
+    hdr = struct.pack("<QQQ", FAMFS_LOG_MAGIC, FAMFS_LOG_LEN, last_index)
+    hdr += struct.pack("<Ixxxx", zlib.crc32(hdr))
+    hdr += struct.pack("<QQ", nentries, nentries)
+    assert len(hdr) == LOG_HDR_SIZE
+    return hdr
+
+
+def relpath(path):
+    raw = path.encode()
+    assert len(raw) < FAMFS_MAX_PATHLEN
+    return raw.ljust(FAMFS_MAX_PATHLEN, b"\0")
+
+
+def simple_extents(extents):
+    se = b"".join(struct.pack("<QQQ", 0, off, ln) for off, ln in extents)
+    se = se.ljust(24 * FAMFS_MAX_SIMPLE_EXTENTS, b"\0")
+    return struct.pack("<Ixxxx", len(extents)) + se
+
+
+def simple_fmap(extents):
+    inner = simple_extents(extents).ljust(FMAP_INNER_SIZE, b"\0")
+    return struct.pack("<Ixxxx", FAMFS_EXT_SIMPLE) + inner
+
+
+def interleaved_fmap(chunk_size, strips):
+    ie = struct.pack("<QQ", len(strips), chunk_size)
+    ie += simple_extents(strips)[8:]
+    inner = struct.pack("<Ixxxx", 1) + ie
+    assert len(inner) == FMAP_INNER_SIZE
+    return struct.pack("<Ixxxx", FAMFS_EXT_INTERLEAVE) + inner
+
+
+def entry(seqnum, entry_type, payload):
+    le = struct.pack("<QIxxxx", seqnum, entry_type)
+    le += payload.ljust(ENTRY_UNION_SIZE, b"\0")
+    le += struct.pack("<II", zlib.crc32(le), 0)
+    assert len(le) == ENTRY_SIZE
+    return le
+
+
+def file_entry(seqnum, path, size, fmap, mode=0o644, uid=0, gid=0, flags=FAMFS_FM_ALL_HOSTS_RW):
+    fm = struct.pack("<QIIII", size, flags, uid, gid, mode) + relpath(path) + fmap
+    return entry(seqnum, FAMFS_LOG_FILE, fm)
+
+
+def mkdir_entry(seqnum, path, mode=0o755, uid=0, gid=0):
+    md = struct.pack("<III", uid, gid, mode) + relpath(path)
+    return entry(seqnum, FAMFS_LOG_MKDIR, md)
+
+
+MB2 = 0x200000
+DATA = FAMFS_LOG_OFFSET + FAMFS_LOG_LEN  # first free byte after the log
+
+IMAGES = {
+    "simple": [
+        lambda n: file_entry(n, "file0", 4096, simple_fmap([(DATA, MB2)])),
+        lambda n: mkdir_entry(n, "dir0"),
+        lambda n: file_entry(n, "dir0/file1", 3 * MB2, simple_fmap([(DATA + MB2, 3 * MB2)]),
+                             mode=0o600, uid=1000, gid=1000),
+        lambda n: mkdir_entry(n, "dir0/dir1", mode=0o700, uid=1000, gid=1000),
+    ],
+    "multi_extent": [
+        lambda n: mkdir_entry(n, "data"),
+        lambda n: file_entry(n, "data/multi", 5 * MB2,
+                             simple_fmap([(DATA, MB2), (DATA + 4 * MB2, 2 * MB2), (DATA + 8 * MB2, 2 * MB2)]),
+                             flags=FAMFS_FM_ALL_HOSTS_RO),
+    ],
+    "interleaved": [
+        lambda n: mkdir_entry(n, "stripes"),
+        lambda n: file_entry(n, "stripes/striped", 4 * MB2,
+                             interleaved_fmap(MB2, [(DATA, 2 * MB2), (DATA + 16 * MB2, 2 * MB2)])),
+        lambda n: file_entry(n, "stripes/plain", 100, simple_fmap([(DATA + 2 * MB2, MB2)])),
+    ],
+}
+
+
+def main():
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, entries in IMAGES.items():
+        with open(os.path.join(here, name + ".sb"), "wb") as f:
+            f.write(superblock(name))
+        with open(os.path.join(here, name + ".log"), "wb") as f:
+            f.write(log_header(len(entries)))
+            for seqnum, make in enumerate(entries):
+                f.write(make(seqnum))
+
+
+if __name__ == "__main__":
+    main()
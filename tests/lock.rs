@@ -0,0 +1,28 @@
+// Log locking: the flock on the image keeps a second mutator out.
+
+mod common;
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use common::new_famfs;
+use famfs_rs::Famfs;
+
+#[test]
+fn try_lock_log_excludes_other_holders() {
+    let (image, mut fs) = new_famfs();
+    let mut other = Famfs::open(image.path()).unwrap();
+
+    let mut log = fs.try_lock_log().unwrap();
+    assert_eq!(other.try_lock_log().err().map(|err| err.kind()), Some(ErrorKind::WouldBlock));
+    log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    drop(log);
+
+    let mut log = other.try_lock_log().unwrap();
+    log.make_dir(Path::new("dir1"), 0o755, 0, 0).unwrap();
+    assert_eq!(fs.try_lock_log().err().map(|err| err.kind()), Some(ErrorKind::WouldBlock));
+    drop(log);
+
+    assert!(fs.stat(Path::new("dir1")).is_some());
+    assert!(other.stat(Path::new("dir0")).is_some());
+}
@@ -0,0 +1,31 @@
+// stat: what the log says about a path, decoded out of its entry.
+
+mod common;
+
+use std::path::Path;
+
+use common::{new_famfs, MB2};
+use famfs_rs::meta::{FAMFS_FM_ALL_HOSTS_RO, FAMFS_FM_ALL_HOSTS_RW};
+
+#[test]
+fn stat_reports_the_logged_metadata() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("dir0/file0"), 0o644, 1000, 1000, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("file1"), 0o600, 0, 0, 3 * MB2, FAMFS_FM_ALL_HOSTS_RO).unwrap();
+    }
+
+    let metadata = fs.stat(Path::new("dir0/file0")).unwrap();
+    assert!(metadata.is_file());
+    assert_eq!((metadata.len(), metadata.mode(), metadata.uid(), metadata.gid()), (4096, 0o644, 1000, 1000));
+    assert_eq!((metadata.flags(), metadata.seqnum(), metadata.extents().len()), (FAMFS_FM_ALL_HOSTS_RW, 1, 1));
+
+    let metadata = fs.stat(Path::new("file1")).unwrap();
+    assert_eq!((metadata.len(), metadata.flags()), (3 * MB2, FAMFS_FM_ALL_HOSTS_RO));
+
+    assert!(fs.stat(Path::new("dir0")).unwrap().is_dir());
+    assert!(fs.stat(Path::new("./dir0/")).unwrap().is_dir());
+    assert!(fs.stat(Path::new("dir")).is_none());
+}
@@ -0,0 +1,53 @@
+// Playing the log onto a directory tree at a mount point.
+
+mod common;
+
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use common::{new_famfs, MB2};
+use famfs_rs::internal::MountChange;
+use famfs_rs::meta::FAMFS_FM_ALL_HOSTS_RW;
+
+#[test]
+fn logplay_mount_creates_what_is_missing() {
+    let (_image, mut fs) = new_famfs();
+    let mpt = tempfile::tempdir().unwrap();
+    // our own uid and gid so no chown is needed
+    let owner = std::fs::metadata(mpt.path()).unwrap();
+    let (uid, gid) = (owner.uid(), owner.gid());
+
+    let mut log = fs.lock_log().unwrap();
+    log.make_dir(Path::new("dir0"), 0o750, uid, gid).unwrap();
+    log.make_file(Path::new("dir0/file0"), 0o640, uid, gid, MB2 + 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("file1"), 0o600, uid, gid, 10, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+
+    let expected = vec![
+        MountChange::Mkdir(PathBuf::from("dir0")),
+        MountChange::Create(PathBuf::from("dir0/file0")),
+        MountChange::Create(PathBuf::from("file1")),
+    ];
+
+    let (stats, changes) = log.logplay_mount(mpt.path(), true).unwrap();
+    assert_eq!((stats.d_created, stats.f_created, stats.f_existed, stats.d_existed), (1, 2, 0, 0));
+    assert_eq!(changes, expected);
+    assert_eq!(std::fs::read_dir(mpt.path()).unwrap().count(), 0);
+
+    let (stats, changes) = log.logplay_mount(mpt.path(), false).unwrap();
+    assert_eq!((stats.d_created, stats.f_created, stats.f_errs, stats.d_errs), (1, 2, 0, 0));
+    assert_eq!(changes, expected);
+    let metadata = std::fs::metadata(mpt.path().join("dir0/file0")).unwrap();
+    assert_eq!((metadata.len(), metadata.permissions().mode() & 0o7777), (MB2 + 1, 0o640));
+    let metadata = std::fs::metadata(mpt.path().join("dir0")).unwrap();
+    assert!(metadata.is_dir());
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o750);
+
+    // everything is there now, only the new file is made
+    log.make_file(Path::new("file2"), 0o644, uid, gid, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    let (stats, changes) = log.logplay_mount(mpt.path(), false).unwrap();
+    assert_eq!((stats.d_created, stats.f_created, stats.f_existed, stats.d_existed), (0, 1, 2, 1));
+    assert_eq!(changes, [MountChange::Create(PathBuf::from("file2"))]);
+
+    let file = tempfile::NamedTempFile::new().unwrap();
+    assert_eq!(log.logplay_mount(file.path(), true).err().map(|err| err.kind()), Some(std::io::ErrorKind::NotADirectory));
+}
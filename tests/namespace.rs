@@ -0,0 +1,113 @@
+// Tailing the log: picking up only the entries appended since the last
+// replay, and rebuilding when the log is reinitialized. Famfs lookups
+// tail the log the same way.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use common::new_famfs;
+use famfs_rs::Famfs;
+use famfs_rs::meta::{famfs_log, FAMFS_FM_ALL_HOSTS_RW};
+use famfs_rs::namespace::{LogEvent, LogWatcher, Namespace};
+
+fn paths(events: &[LogEvent]) -> Vec<PathBuf> {
+    events
+        .iter()
+        .map(|event| match event {
+            LogEvent::Create { path, .. }
+            | LogEvent::Mkdir { path, .. }
+            | LogEvent::Delete { path, .. }
+            | LogEvent::SetAttr { path, .. }
+            | LogEvent::Symlink { path, .. }
+            | LogEvent::Resize { path, .. } => path.clone(),
+            LogEvent::Rename { to, .. } => to.clone(),
+        })
+        .collect()
+}
+
+#[test]
+fn apply_new_only_sees_new_entries() {
+    let (image, mut fs) = new_famfs();
+    let log: *const famfs_log = fs.log();
+
+    fs.lock_log().unwrap().make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    let mut ns = unsafe { Namespace::replay(log) };
+    assert_eq!(ns.next_index(), 1);
+    assert!(unsafe { ns.apply_new(log) }.is_empty());
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("dir0/file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.rename(Path::new("dir0"), Path::new("dir1")).unwrap();
+    }
+    let events = unsafe { ns.apply_new(log) };
+    assert!(matches!(events[0], LogEvent::Create { index: 1, .. }));
+    assert!(matches!(events[1], LogEvent::Rename { index: 2, .. }));
+    assert_eq!(paths(&events), ["dir0/file0", "dir1"].map(PathBuf::from));
+    assert_eq!((ns.next_index(), ns.last_seqnum()), (3, Some(2)));
+    assert!(ns.lookup(Path::new("dir1/file0")).is_some());
+    assert!(unsafe { ns.apply_new(log) }.is_empty());
+
+    // a reinitialized log goes back to index 0, the namespace starts over
+    let mut fresh = Famfs::mkfs(image.path(), true).unwrap();
+    fresh.lock_log().unwrap().make_dir(Path::new("new"), 0o755, 0, 0).unwrap();
+    let events = unsafe { ns.apply_new(log) };
+    assert_eq!(paths(&events), [PathBuf::from("new")]);
+    assert_eq!(ns.next_index(), 1);
+    assert!(ns.lookup(Path::new("dir1")).is_none());
+    assert_eq!(ns.iter().count(), 1);
+}
+
+#[test]
+fn log_watcher_reports_appends() {
+    let (_image, mut fs) = new_famfs();
+    let log: *const famfs_log = fs.log();
+
+    fs.lock_log().unwrap().make_dir(Path::new("old"), 0o755, 0, 0).unwrap();
+    let ns = unsafe { Namespace::replay(log) };
+    let mut watcher = unsafe { LogWatcher::new(log, ns, Duration::from_millis(1)) };
+    assert!(watcher.poll().is_empty());
+
+    fs.lock_log().unwrap().make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    assert_eq!(paths(&watcher.poll()), [PathBuf::from("dir0")]);
+    assert!(watcher.poll().is_empty());
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("dir0/file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.delete_file(Path::new("dir0/file0")).unwrap();
+    }
+    let events: Vec<LogEvent> = watcher.by_ref().take(2).collect();
+    assert!(matches!(events[0], LogEvent::Create { .. }));
+    assert!(matches!(events[1], LogEvent::Delete { .. }));
+    assert!(watcher.poll().is_empty());
+
+    let ns = watcher.into_namespace();
+    assert_eq!(ns.next_index(), 4);
+    assert!(ns.lookup(Path::new("dir0")).is_some());
+    assert!(ns.lookup(Path::new("dir0/file0")).is_none());
+}
+
+#[test]
+fn lookups_catch_up_with_other_handles() {
+    let (image, mut writer) = new_famfs();
+    let reader = Famfs::open(image.path()).unwrap();
+
+    writer.lock_log().unwrap().make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    assert!(reader.canonicalize(Path::new("dir0")).is_ok());
+    assert_eq!(reader.replay().next_index(), 1);
+
+    writer.lock_log().unwrap().make_file(Path::new("dir0/file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    assert!(reader.get_file_read_only(Path::new("dir0/file0")).is_some());
+    assert_eq!(reader.replay().next_index(), 2);
+
+    // a reinitialized log is picked up too
+    let mut fresh = Famfs::mkfs(image.path(), true).unwrap();
+    fresh.lock_log().unwrap().make_dir(Path::new("new"), 0o755, 0, 0).unwrap();
+    assert!(reader.get_file_read_only(Path::new("dir0/file0")).is_none());
+    let ns = reader.replay();
+    assert_eq!(ns.next_index(), 1);
+    assert!(ns.lookup(Path::new("new")).is_some());
+}
@@ -0,0 +1,54 @@
+// The rules for paths that go into the log.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use famfs_rs::meta::FAMFS_MAX_PATHLEN;
+use famfs_rs::relpath::{RelPath, RelPathError};
+
+fn relpath(path: &str) -> Result<RelPath, RelPathError> {
+    RelPath::new(Path::new(path))
+}
+
+#[test]
+fn paths_are_normalized() {
+    for (path, normal) in [("a", "a"), ("a/./b/", "a/b"), ("./a", "a"), ("a/b/.", "a/b"), ("a/.", "a"), ("a//b", "a/b"), ("a/b//", "a/b"), (".//a///b", "a/b")] {
+        assert_eq!(relpath(path).unwrap().as_path(), Path::new(normal), "{path}");
+    }
+    assert_eq!(relpath("a/./b/"), relpath("a/b"));
+    assert_eq!(relpath("a//b"), relpath("a/b"));
+    assert_eq!(relpath("a/b/c").unwrap().parent(), Some(relpath("a/b").unwrap()));
+    assert_eq!(relpath("a").unwrap().parent(), None);
+}
+
+#[test]
+fn bad_paths_are_refused() {
+    assert_eq!(relpath("/a"), Err(RelPathError::Absolute));
+    assert_eq!(relpath("/"), Err(RelPathError::Absolute));
+    assert_eq!(relpath(".."), Err(RelPathError::ParentDir));
+    assert_eq!(relpath("a/../b"), Err(RelPathError::ParentDir));
+    assert_eq!(relpath(""), Err(RelPathError::Empty));
+    assert_eq!(relpath("."), Err(RelPathError::Empty));
+    assert_eq!(relpath("./"), Err(RelPathError::Empty));
+    assert_eq!(relpath(".//."), Err(RelPathError::Empty));
+    assert_eq!(RelPath::new(Path::new(OsStr::from_bytes(b"a/b\0c"))), Err(RelPathError::Nul));
+
+    assert_eq!(relpath("a/..").unwrap_err().errno(), -(libc::EINVAL as i64));
+    assert_eq!(relpath(&"x".repeat(FAMFS_MAX_PATHLEN)).unwrap_err().errno(), -(libc::ENAMETOOLONG as i64));
+}
+
+#[test]
+fn paths_leave_room_for_the_terminator() {
+    let longest = "x".repeat(FAMFS_MAX_PATHLEN - 1);
+    assert_eq!(relpath(&longest).unwrap().as_bytes().len(), 79);
+    assert_eq!(relpath(&"x".repeat(FAMFS_MAX_PATHLEN)), Err(RelPathError::TooLong));
+    // the limit is on the normalized path
+    assert!(relpath(&format!("./{longest}/")).is_ok());
+    assert_eq!(relpath(&format!("{}//{}", &longest[..39], &longest[40..])).unwrap().as_bytes().len(), 79);
+
+    let raw = relpath(&longest).unwrap().to_relpath();
+    assert_eq!(raw[FAMFS_MAX_PATHLEN - 1], 0);
+    assert_eq!(RelPath::from_relpath(&raw), relpath(&longest));
+    assert_eq!(RelPath::from_relpath(&[b'x'; FAMFS_MAX_PATHLEN]), Err(RelPathError::TooLong));
+}
@@ -0,0 +1,73 @@
+// Renaming files and directories, the nodes move but the data stays put.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use common::{new_famfs, MB2};
+use famfs_rs::shadow;
+use famfs_rs::meta::FAMFS_FM_ALL_HOSTS_RW;
+
+#[test]
+fn rename_moves_nodes() {
+    let (_image, mut fs) = new_famfs();
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+        log.make_dir(Path::new("dir0/sub"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("dir0/sub/file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new(".tmp"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("other"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    }
+    let extents = fs.stat(Path::new(".tmp")).unwrap().extents().to_vec();
+    fs.get_file(Path::new(".tmp")).unwrap().write_all(b"published").unwrap();
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        assert_eq!(log.rename(Path::new(".tmp"), Path::new("other")), Err(-(libc::EEXIST as i64)));
+        assert_eq!(log.rename(Path::new("nofile"), Path::new("x")), Err(-(libc::ENOENT as i64)));
+        assert_eq!(log.rename(Path::new("dir0"), Path::new("dir0/sub/dir0")), Err(-(libc::EINVAL as i64)));
+        assert_eq!(log.rename(Path::new(".tmp"), Path::new("nodir/x")), Err(-(libc::ENOENT as i64)));
+        assert_eq!(log.rename(Path::new(".tmp"), Path::new("other/x")), Err(-(libc::ENOTDIR as i64)));
+        log.rename(Path::new(".tmp"), Path::new(".tmp/")).unwrap();
+
+        log.rename(Path::new(".tmp"), Path::new("dir0/sub/published")).unwrap();
+        log.rename(Path::new("dir0"), Path::new("dir1")).unwrap();
+    }
+
+    // the data stays where it was written
+    let metadata = fs.stat(Path::new("dir1/sub/published")).unwrap();
+    assert_eq!(metadata.extents(), &extents[..]);
+    let mut data = [0; 9];
+    fs.get_file(Path::new("dir1/sub/published")).unwrap().read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"published");
+
+    assert!(fs.stat(Path::new(".tmp")).is_none());
+    assert!(fs.stat(Path::new("dir0")).is_none());
+    assert!(fs.stat(Path::new("dir1/sub/file0")).is_some());
+    let paths: Vec<PathBuf> = fs.walk(Path::new("")).unwrap().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(paths, ["dir1", "dir1/sub", "dir1/sub/file0", "dir1/sub/published", "other"].map(PathBuf::from));
+
+    // a renamed file can still be grown and deleted under its new name
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.set_len(Path::new("dir1/sub/published"), 2 * MB2).unwrap();
+        log.delete_file(Path::new("dir1/sub/file0")).unwrap();
+    }
+    assert!(fs.stat(Path::new("dir1/sub/published")).is_some());
+    assert!(fs.stat(Path::new("dir1/sub/file0")).is_none());
+
+    let report = fs.fsck();
+    assert!(report.is_clean(), "{report}");
+    assert_eq!(report.renames, 2);
+    // 3 is the C tools' invalid type, renames are logged after it
+    assert!(fs.dump_log().to_json().contains("\"type\":\"rename\",\"raw_type\":4,"));
+
+    let shadow_root = tempfile::tempdir().unwrap();
+    let stats = fs.lock_log().unwrap().logplay_shadow(shadow_root.path()).unwrap();
+    assert_eq!(stats.yaml_errs, 0);
+    let yaml = std::fs::read_to_string(shadow::shadow_path(shadow_root.path(), Path::new("dir1/sub/published"))).unwrap();
+    assert!(yaml.contains("dir1/sub/published"));
+}
@@ -0,0 +1,170 @@
+// Growing and shrinking files, which relogs their fmap.
+
+mod common;
+
+use std::cell::Cell;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use common::{new_famfs, FailingCommits, MB2};
+use famfs_rs::{FamfsMetadataInterface, MMAPed};
+use famfs_rs::internal::famfs_locked_log;
+use famfs_rs::meta::{famfs_log, famfs_log_file_meta, famfs_log_fmap, FAMFS_FM_ALL_HOSTS_RW, FAMFS_MAX_SIMPLE_EXTENTS, MIN_DEVSIZE};
+use famfs_rs::relpath::RelPath;
+use famfs_rs::namespace::{LogEvent, Namespace};
+
+#[test]
+fn set_len_relogs_the_file() {
+    let (_image, mut fs) = new_famfs();
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("grow"), 0o640, 7, 8, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("next"), 0o644, 0, 0, MB2, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    }
+    fs.get_file(Path::new("grow")).unwrap().write_all(b"kept").unwrap();
+
+    // the slack in the first extent goes first, then a new extent
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.set_len(Path::new("grow"), MB2).unwrap();
+        assert_eq!(log.stat(Path::new("grow")).unwrap().extents().len(), 1);
+        log.set_len(Path::new("grow"), 3 * MB2 + 1).unwrap();
+        assert_eq!(log.set_len(Path::new("nofile"), 1), Err(-(libc::ENOENT as i64)));
+    }
+
+    let metadata = fs.stat(Path::new("grow")).unwrap();
+    assert_eq!((metadata.len(), metadata.mode(), metadata.uid(), metadata.gid()), (3 * MB2 + 1, 0o640, 7, 8));
+    assert_eq!(metadata.extents().iter().map(|se| se.se_len).collect::<Vec<_>>(), [MB2, 3 * MB2]);
+
+    let mut file = fs.get_file(Path::new("grow")).unwrap();
+    let mut data = [0; 4];
+    file.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"kept");
+    file.seek(SeekFrom::End(-1)).unwrap();
+    file.write_all(b"!").unwrap();
+    drop(file);
+
+    // shrinking keeps the extents, the space stays the file's
+    fs.lock_log().unwrap().set_len(Path::new("grow"), 4).unwrap();
+    assert_eq!(fs.stat(Path::new("grow")).unwrap().extents().len(), 2);
+    assert!(fs.fsck().is_clean());
+    assert_eq!(fs.read_dir(Path::new("")).unwrap().len(), 2);
+}
+
+#[test]
+fn set_len_logs_one_entry() {
+    let (_image, mut fs) = new_famfs();
+    let log: *const famfs_log = fs.log();
+
+    fs.lock_log().unwrap().make_file(Path::new("grow"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    fs.set_permissions(Path::new("grow"), 0o600).unwrap();
+    let mut ns = unsafe { Namespace::replay(log) };
+
+    // a tailer never sees the file go away
+    fs.lock_log().unwrap().set_len(Path::new("grow"), 2 * MB2).unwrap();
+    let events = unsafe { ns.apply_new(log) };
+    assert_eq!(events, [LogEvent::Resize { path: PathBuf::from("grow"), index: 2, seqnum: 2 }]);
+    assert_eq!(fs.stat(Path::new("grow")).unwrap().mode(), 0o600);
+
+    let json = fs.dump_log().to_json();
+    assert!(json.contains("\"type\":\"resize\",\"raw_type\":7,\"path\":\"grow\",\"uid\":0,\"gid\":0,\"mode\":384,\"size\":4194304"));
+
+    // the bitmap built from the log agrees with the allocator
+    let allocated = fs.lock_log().unwrap().allocated_bytes();
+    let report = fs.fsck();
+    assert!(report.is_clean(), "{report}");
+    assert_eq!((report.resizes, report.allocated_bytes), (1, allocated));
+
+    fs.lock_log().unwrap().delete_file(Path::new("grow")).unwrap();
+    assert!(fs.stat(Path::new("grow")).is_none());
+    assert!(fs.fsck().is_clean());
+}
+
+#[test]
+fn failed_set_len_gives_the_space_back() {
+    let (image, mut fs) = new_famfs();
+    fs.lock_log().unwrap().make_file(Path::new("grow"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    drop(fs);
+
+    let fail = Rc::new(Cell::new(false));
+    let mut iface = FailingCommits { mmaped: MMAPed::open(image.path()).unwrap(), fail: fail.clone() };
+    let lfd = iface.lock_file().unwrap();
+    let mut log = unsafe { famfs_locked_log::from_log(&mut iface, lfd) }.unwrap();
+    let allocated = log.allocated_bytes();
+
+    fail.set(true);
+    assert_eq!(log.set_len(Path::new("grow"), 4 * MB2), Err(-(libc::EIO as i64)));
+    assert_eq!(log.allocated_bytes(), allocated);
+    assert_eq!(log.stat(Path::new("grow")).unwrap().len(), 4096);
+
+    fail.set(false);
+    log.set_len(Path::new("grow"), 4 * MB2).unwrap();
+    assert_eq!(log.allocated_bytes(), allocated + 3 * MB2);
+}
+
+#[test]
+fn set_len_grows_the_last_extent_in_place() {
+    let (_image, mut fs) = new_famfs();
+    let mut log = fs.lock_log().unwrap();
+    log.make_file(Path::new("a"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("b"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+
+    // growing them in turn leaves each extent of a followed by one of b
+    for units in 2..=FAMFS_MAX_SIMPLE_EXTENTS as u64 {
+        log.set_len(Path::new("a"), units * MB2).unwrap();
+        log.set_len(Path::new("b"), units * MB2).unwrap();
+    }
+    assert_eq!(log.stat(Path::new("a")).unwrap().extents().len(), FAMFS_MAX_SIMPLE_EXTENTS);
+
+    // with b gone the last extent of a can take its place, the map is full
+    // but nothing needs adding to it
+    log.delete_file(Path::new("b")).unwrap();
+    log.set_len(Path::new("a"), 17 * MB2).unwrap();
+    let extents = log.stat(Path::new("a")).unwrap().extents().to_vec();
+    assert_eq!(extents.len(), FAMFS_MAX_SIMPLE_EXTENTS);
+    assert_eq!(extents.last().unwrap().se_len, 2 * MB2);
+
+    // once the space after it is taken there is nowhere to go
+    log.make_file(Path::new("c"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    assert_eq!(log.set_len(Path::new("a"), 18 * MB2), Err(-(libc::EFBIG as i64)));
+    drop(log);
+
+    assert!(fs.fsck().is_clean());
+}
+
+#[test]
+fn set_len_fails_with_enospc_when_the_device_is_full() {
+    let (_image, mut fs) = new_famfs();
+    let mut log = fs.lock_log().unwrap();
+    log.make_file(Path::new("grow"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    let allocated = log.allocated_bytes();
+
+    assert_eq!(log.set_len(Path::new("grow"), MIN_DEVSIZE as u64), Err(-(libc::ENOSPC as i64)));
+    assert_eq!(log.allocated_bytes(), allocated);
+    assert_eq!(log.stat(Path::new("grow")).unwrap().len(), 1);
+}
+
+#[test]
+fn fsck_flags_resizes_onto_another_files_space() {
+    let (image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_file(Path::new("a"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_file(Path::new("b"), 0o644, 0, 0, 1, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    }
+    let mut extents = fs.stat(Path::new("a")).unwrap().extents().to_vec();
+    extents.extend(fs.stat(Path::new("b")).unwrap().extents());
+
+    // a resize that hands a the space b holds
+    let mut mmaped = MMAPed::open(image.path()).unwrap();
+    let log = unsafe { mmaped.log().as_mut() };
+    let relpath = RelPath::new(Path::new("a")).unwrap().to_relpath();
+    let resized = famfs_log_file_meta::new(relpath, 2 * MB2, FAMFS_FM_ALL_HOSTS_RW, 0o644, 0, 0, famfs_log_fmap::simple(&extents).unwrap());
+    unsafe { log.log_resize(&resized, &mut mmaped) }.unwrap();
+
+    let report = fs.fsck();
+    assert_eq!(report.overlapping_units, 1);
+    assert!(!report.is_clean());
+}
@@ -0,0 +1,61 @@
+// Changing the mode and owner of logged nodes.
+
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+
+use common::{new_famfs, MB2};
+use famfs_rs::shadow;
+use famfs_rs::access::Credentials;
+use famfs_rs::meta::FAMFS_FM_ALL_HOSTS_RW;
+
+#[test]
+fn setattr_overrides_the_logged_attributes() {
+    let (_image, mut fs) = new_famfs();
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("dir0"), 0o700, 0, 0).unwrap();
+        log.make_file(Path::new("dir0/big"), 0o600, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    }
+    let user = Credentials::new(1000, 1000, vec![]);
+    let big = Path::new("dir0/big");
+    assert_eq!(fs.open_file(big, &user, true).err(), Some(-(libc::EACCES as i64)));
+
+    fs.set_owner(Path::new("dir0"), None, Some(1000)).unwrap();
+    fs.set_permissions(Path::new("dir0"), 0o750).unwrap();
+    fs.set_owner(big, Some(1000), Some(1000)).unwrap();
+    assert_eq!(fs.set_permissions(big, 0o10644), Err(-(libc::EINVAL as i64)));
+    assert_eq!(fs.set_owner(Path::new("nofile"), Some(1), None), Err(-(libc::ENOENT as i64)));
+
+    let metadata = fs.stat(Path::new("dir0")).unwrap();
+    assert_eq!((metadata.mode(), metadata.uid(), metadata.gid()), (0o750, 0, 1000));
+    let metadata = fs.stat(big).unwrap();
+    assert_eq!((metadata.mode(), metadata.uid(), metadata.gid()), (0o600, 1000, 1000));
+    fs.open_file(big, &user, true).unwrap().write_all(b"mine").unwrap();
+
+    // the attributes follow the node through a rename and a relog
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.rename(big, Path::new("dir0/moved")).unwrap();
+        log.set_len(Path::new("dir0/moved"), 2 * MB2).unwrap();
+    }
+    let entries = fs.read_dir(Path::new("dir0")).unwrap();
+    let metadata = entries[0].metadata();
+    assert_eq!((metadata.len(), metadata.mode(), metadata.uid(), metadata.gid()), (2 * MB2, 0o600, 1000, 1000));
+
+    let report = fs.fsck();
+    assert!(report.is_clean(), "{report}");
+    assert_eq!(report.setattrs, 3);
+
+    let dump = fs.dump_log();
+    assert!(dump.to_json().contains("\"type\":\"setattr\",\"raw_type\":5,\"path\":\"dir0\",\"uid\":0,\"gid\":1000"));
+
+    let shadow_root = tempfile::tempdir().unwrap();
+    let stats = fs.lock_log().unwrap().logplay_shadow(shadow_root.path()).unwrap();
+    assert_eq!(stats.yaml_errs, 0);
+    let yaml = std::fs::read_to_string(shadow::shadow_path(shadow_root.path(), Path::new("dir0/moved"))).unwrap();
+    let file_meta = shadow::parse_file_yaml(&yaml).unwrap();
+    assert_eq!((file_meta.fm_mode, file_meta.fm_uid, file_meta.fm_gid), (0o600, 1000, 1000));
+}
@@ -0,0 +1,49 @@
+// Playing the log into a shadow yaml tree, and keeping a shadow played
+// earlier in step with the log.
+
+mod common;
+
+use std::path::Path;
+
+use common::{new_famfs, MB2};
+use famfs_rs::shadow::{parse_file_yaml, shadow_path};
+use famfs_rs::meta::FAMFS_FM_ALL_HOSTS_RW;
+
+#[test]
+fn replays_update_the_shadow() {
+    let (_image, mut fs) = new_famfs();
+    let shadow_root = tempfile::tempdir().unwrap();
+    let shadow = |path: &str| shadow_path(shadow_root.path(), Path::new(path));
+
+    let mut log = fs.lock_log().unwrap();
+    log.make_dir(Path::new("dir0"), 0o755, 0, 0).unwrap();
+    log.make_file(Path::new("dir0/file0"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("grow"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("gone"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+    log.make_file(Path::new("same"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+
+    let stats = log.logplay_shadow(shadow_root.path()).unwrap();
+    assert_eq!((stats.f_created, stats.f_existed, stats.d_created, stats.yaml_errs), (4, 0, 1, 0));
+
+    log.set_len(Path::new("grow"), 2 * MB2).unwrap();
+    log.delete_file(Path::new("gone")).unwrap();
+    log.rename(Path::new("dir0"), Path::new("dir1")).unwrap();
+    log.set_permissions(Path::new("same"), 0o600).unwrap();
+    // something in the shadow the log never had
+    std::fs::write(shadow("stray"), "").unwrap();
+
+    let stats = log.logplay_shadow(shadow_root.path()).unwrap();
+    assert_eq!((stats.f_created, stats.f_existed, stats.d_created, stats.yaml_errs), (3, 0, 1, 0));
+    assert_eq!((stats.f_errs, stats.d_errs), (0, 0));
+    assert!(!shadow("gone").exists() && !shadow("dir0").exists() && !shadow("stray").exists());
+    assert!(shadow("dir1/file0").is_file());
+
+    let yaml = std::fs::read_to_string(shadow("grow")).unwrap();
+    assert_eq!(parse_file_yaml(&yaml).unwrap().fm_size, 2 * MB2);
+    let yaml = std::fs::read_to_string(shadow("same")).unwrap();
+    assert_eq!(parse_file_yaml(&yaml).unwrap().fm_mode, 0o600);
+
+    // nothing changed, nothing to write
+    let stats = log.logplay_shadow(shadow_root.path()).unwrap();
+    assert_eq!((stats.f_created, stats.f_existed, stats.d_existed, stats.yaml_checked), (0, 3, 1, 3));
+}
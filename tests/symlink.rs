@@ -0,0 +1,114 @@
+// Symlinks: logging them and following them on lookup.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use common::new_famfs;
+use famfs_rs::access::Credentials;
+use famfs_rs::meta::FAMFS_FM_ALL_HOSTS_RW;
+use famfs_rs::namespace::NodeKind;
+
+#[test]
+fn symlinks_resolve_on_lookup() {
+    let (_image, mut fs) = new_famfs();
+
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("v41"), 0o755, 0, 0).unwrap();
+        log.make_dir(Path::new("v42"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("v42/data"), 0o644, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_symlink(Path::new("latest"), Path::new("v42"), 0, 0).unwrap();
+        log.make_symlink(Path::new("v41/up"), Path::new("../latest/data"), 0, 0).unwrap();
+        log.make_symlink(Path::new("v41/abs"), Path::new("/v42/data"), 0, 0).unwrap();
+        log.make_symlink(Path::new("loop0"), Path::new("loop1"), 0, 0).unwrap();
+        log.make_symlink(Path::new("loop1"), Path::new("loop0"), 0, 0).unwrap();
+        log.make_symlink(Path::new("dangling"), Path::new("nowhere"), 0, 0).unwrap();
+
+        assert_eq!(log.make_symlink(Path::new("latest"), Path::new("v41"), 0, 0), Err(-(libc::EEXIST as i64)));
+        assert_eq!(log.make_symlink(Path::new("empty"), Path::new(""), 0, 0), Err(-(libc::ENOENT as i64)));
+        let long = "x".repeat(80);
+        assert_eq!(log.make_symlink(Path::new("long"), Path::new(&long), 0, 0), Err(-(libc::ENAMETOOLONG as i64)));
+    }
+    fs.get_file(Path::new("latest/data")).unwrap().write_all(b"v42").unwrap();
+
+    // lookups without following see the links themselves
+    let metadata = fs.stat(Path::new("latest")).unwrap();
+    assert!(metadata.is_symlink());
+    assert_eq!((metadata.len(), metadata.mode()), (3, 0o777));
+    assert_eq!(fs.read_link(Path::new("latest")), Ok(PathBuf::from("v42")));
+    assert_eq!(fs.read_link(Path::new("latest/data")).err(), Some(-(libc::EINVAL as i64)));
+    for path in ["", ".", "v41/..", "v42"] {
+        assert_eq!(fs.read_link(Path::new(path)).err(), Some(-(libc::EINVAL as i64)), "{path}");
+    }
+    assert_eq!(fs.read_link(Path::new("nowhere")).err(), Some(-(libc::ENOENT as i64)));
+
+    for path in ["latest/data", "v41/up", "v41/abs", "./v41/../latest/./data"] {
+        assert_eq!(fs.canonicalize(Path::new(path)), Ok(PathBuf::from("v42/data")), "{path}");
+    }
+    assert_eq!(fs.canonicalize(Path::new("latest")), Ok(PathBuf::from("v42")));
+    assert_eq!(fs.canonicalize(Path::new("loop0")), Err(-(libc::ELOOP as i64)));
+    assert_eq!(fs.canonicalize(Path::new("dangling")), Err(-(libc::ENOENT as i64)));
+    assert_eq!(fs.canonicalize(Path::new("v42/data/x")), Err(-(libc::ENOTDIR as i64)));
+
+    let root = Credentials::new(0, 0, vec![]);
+    let mut data = [0; 3];
+    fs.open_file(Path::new("v41/up"), &root, false).unwrap().read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"v42");
+    assert_eq!(fs.open_file(Path::new("loop1"), &root, false).err(), Some(-(libc::ELOOP as i64)));
+
+    let kinds: Vec<NodeKind> = fs.read_dir(Path::new("v41")).unwrap().iter().map(|entry| entry.metadata().kind()).collect();
+    assert_eq!(kinds, [NodeKind::Symlink, NodeKind::Symlink]);
+
+    // repointing a link is a rename over a fresh one
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_symlink(Path::new(".latest"), Path::new("v41"), 0, 0).unwrap();
+        log.delete_file(Path::new("latest")).unwrap();
+        log.rename(Path::new(".latest"), Path::new("latest")).unwrap();
+        log.delete_file(Path::new("dangling")).unwrap();
+    }
+    assert_eq!(fs.canonicalize(Path::new("latest")), Ok(PathBuf::from("v41")));
+    assert_eq!(fs.canonicalize(Path::new("v41/up")), Err(-(libc::ENOENT as i64)));
+    assert!(fs.stat(Path::new("dangling")).is_none());
+
+    let report = fs.fsck();
+    assert!(report.is_clean(), "{report}");
+    assert_eq!(report.symlinks, 7);
+    assert!(fs.dump_log().to_json().contains("\"type\":\"symlink\",\"raw_type\":6,\"path\":\"latest\",\"uid\":0,\"gid\":0,\"mode\":511,\"target\":\"v42\""));
+
+    let mpt = tempfile::tempdir().unwrap();
+    let (stats, _) = fs.lock_log().unwrap().logplay_mount(mpt.path(), false).unwrap();
+    assert_eq!((stats.s_logged, stats.f_errs), (7, 0));
+    assert_eq!(std::fs::read_link(mpt.path().join("v41/abs")).unwrap(), mpt.path().join("v42/data"));
+    assert_eq!(std::fs::read_link(mpt.path().join("latest")).unwrap(), Path::new("v41"));
+}
+
+#[test]
+fn lookups_follow_links_on_the_way() {
+    let (_image, mut fs) = new_famfs();
+    {
+        let mut log = fs.lock_log().unwrap();
+        log.make_dir(Path::new("v42"), 0o755, 0, 0).unwrap();
+        log.make_dir(Path::new("v42/sub"), 0o755, 0, 0).unwrap();
+        log.make_file(Path::new("v42/data"), 0o640, 0, 0, 4096, FAMFS_FM_ALL_HOSTS_RW).unwrap();
+        log.make_symlink(Path::new("latest"), Path::new("v42"), 0, 0).unwrap();
+        log.make_symlink(Path::new("v42/sub/up"), Path::new(".."), 0, 0).unwrap();
+    }
+
+    // the last component is described itself, the ones before it followed
+    assert_eq!(fs.stat(Path::new("latest/data")).unwrap().mode(), 0o640);
+    assert!(fs.stat(Path::new("latest")).unwrap().is_symlink());
+    assert!(fs.stat(Path::new("latest/sub/up")).unwrap().is_symlink());
+    assert!(fs.stat(Path::new("latest/sub/up/data")).unwrap().is_file());
+    assert!(fs.lock_log().unwrap().stat(Path::new("latest/data")).is_some());
+
+    // directories are listed under the path they were asked for
+    let listed: Vec<PathBuf> = fs.read_dir(Path::new("latest")).unwrap().iter().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(listed, ["latest/data", "latest/sub"].map(PathBuf::from));
+    let walked: Vec<PathBuf> = fs.walk(Path::new("./latest/")).unwrap().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(walked, ["latest/data", "latest/sub", "latest/sub/up"].map(PathBuf::from));
+    assert_eq!(fs.read_dir(Path::new("latest/sub/up")).unwrap().len(), 2);
+    assert_eq!(fs.read_dir(Path::new("latest/data")).unwrap_err(), -(libc::ENOTDIR as i64));
+}